@@ -0,0 +1,197 @@
+use super::lexer::{Span, Token};
+use crate::word::Word;
+
+// a parsed Simple program, in source order
+pub struct SyntaxTree {
+    pub lines: Vec<Line>,
+}
+
+// a numbered line holding one statement
+pub struct Line {
+    pub number: Label,
+    pub statement: Statement,
+    // span of the statement's command
+    pub span: Span,
+}
+
+// a line number, either labelling a line or targeted by a jump
+#[derive(Clone, Copy)]
+pub struct Label {
+    pub number: i32,
+    pub span: Span,
+}
+
+#[derive(Clone)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+pub enum Statement {
+    Rem,
+    // target is a variable, string variable or array element
+    Input(Expression),
+    Print(Expression),
+    // IF condition GOTO line
+    IfGoto {
+        condition: Condition,
+        target: Label,
+    },
+    // IF condition THEN statement, all on one line
+    IfThen {
+        condition: Condition,
+        statement: Box<Statement>,
+        // span of the command after THEN
+        span: Span,
+    },
+    // IF condition THEN, closed by ENDIF
+    If(Condition),
+    ElseIf(Condition),
+    Else,
+    EndIf,
+    Goto(Label),
+    Gosub(Label),
+    Return,
+    // target is a variable or array element
+    Let {
+        target: Expression,
+        value: Expression,
+    },
+    Dim {
+        name: Identifier,
+        size: i32,
+    },
+    For {
+        counter: Identifier,
+        start: Expression,
+        limit: Expression,
+        step: Option<Expression>,
+    },
+    Next(Option<Identifier>),
+    While(Condition),
+    Wend,
+    Do,
+    // LOOP, LOOP UNTIL condition or LOOP WHILE condition
+    Loop(Option<LoopCondition>),
+    Exit,
+    End,
+    // a statement that failed to parse, kept so the lines around it still compile
+    Invalid(Vec<Token>),
+}
+
+impl Statement {
+    // whether the statement opens or closes a block, so it cannot follow THEN
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self,
+            Statement::If(_)
+                | Statement::ElseIf(_)
+                | Statement::Else
+                | Statement::EndIf
+                | Statement::For { .. }
+                | Statement::Next(_)
+                | Statement::While(_)
+                | Statement::Wend
+                | Statement::Do
+                | Statement::Loop(_)
+        )
+    }
+}
+
+pub enum LoopCondition {
+    Until(Condition),
+    While(Condition),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulus,
+    Exponentiate,
+}
+
+impl Operator {
+    pub fn operation_code(&self) -> u32 {
+        match self {
+            Operator::Add => 0x30,
+            Operator::Subtract => 0x31,
+            Operator::Divide => 0x32,
+            Operator::Multiply => 0x33,
+            Operator::Modulus => 0x34,
+            Operator::Exponentiate => 0x35,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Clone)]
+pub enum ExpressionKind {
+    Number(Word),
+    // string literal without its quotes
+    String(String),
+    // variable or string variable, which ends in $
+    Variable(String),
+    Element {
+        array: String,
+        index: Box<Expression>,
+    },
+    Negate(Box<Expression>),
+    Binary {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+impl Expression {
+    // value of an expression that is just a number, such as 5 or -5
+    pub fn to_constant(&self) -> Option<Word> {
+        match &self.kind {
+            ExpressionKind::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+}
+
+impl Comparison {
+    // the comparison that is true exactly when this one is false
+    pub fn negate(&self) -> Comparison {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterEqual,
+            Comparison::Greater => Comparison::LessEqual,
+            Comparison::LessEqual => Comparison::Greater,
+            Comparison::GreaterEqual => Comparison::Less,
+        }
+    }
+}
+
+pub enum Condition {
+    Compare {
+        comparison: Comparison,
+        left: Expression,
+        right: Expression,
+    },
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
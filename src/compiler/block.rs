@@ -0,0 +1,59 @@
+use super::lexer::Span;
+
+#[derive(Clone)]
+pub enum BlockType {
+    For {
+        variable: String,
+        counter: u32,
+        step: u32,
+        top: u32,
+    },
+    If {
+        // jumps taken when the current branch's condition is false
+        next: Vec<u32>,
+        has_else: bool,
+    },
+    While {
+        top: u32,
+    },
+    Do {
+        top: u32,
+    },
+}
+
+impl BlockType {
+    // command that opens the block
+    pub fn opener(&self) -> &'static str {
+        match self {
+            BlockType::For { .. } => "FOR",
+            BlockType::If { .. } => "IF",
+            BlockType::While { .. } => "WHILE",
+            BlockType::Do { .. } => "DO",
+        }
+    }
+
+    // command that closes the block
+    pub fn closer(&self) -> &'static str {
+        match self {
+            BlockType::For { .. } => "NEXT",
+            BlockType::If { .. } => "ENDIF",
+            BlockType::While { .. } => "WEND",
+            BlockType::Do { .. } => "LOOP",
+        }
+    }
+
+    // whether EXIT can leave the block
+    pub fn is_loop(&self) -> bool {
+        !matches!(self, BlockType::If { .. })
+    }
+}
+
+// a control structure that spans multiple lines
+#[derive(Clone)]
+pub struct Block {
+    pub block_type: BlockType,
+    // command that opened the block
+    pub span: Span,
+    // branches to be patched with the address after the block
+    pub exits: Vec<u32>,
+}
@@ -0,0 +1,730 @@
+use super::{
+    ast::{Comparison, Condition, Expression, ExpressionKind, LoopCondition, Statement},
+    block::{Block, BlockType},
+    lexer::{Span, SyntaxError},
+    table_entry::TableEntryType,
+    Compiler, MemoryLimitExceeded,
+};
+use anyhow::{bail, Result};
+
+impl Compiler {
+    // emits the instructions for one statement
+    pub(super) fn compile_statement(&mut self, statement: &Statement) -> Result<()> {
+        match statement {
+            Statement::Rem => {}
+            Statement::Input(target) => self.compile_input(target)?,
+            Statement::Print(expression) => self.compile_print(expression)?,
+            Statement::IfGoto { condition, target } => {
+                let jumps = self.compile_condition(condition, true)?;
+                self.add_jump_target(&jumps, *target);
+            }
+            Statement::IfThen {
+                condition,
+                statement,
+                span,
+            } => {
+                // skip the statement when the condition is false
+                let next = self.compile_condition(condition, false)?;
+
+                self.compile_statement(statement)
+                    .map_err(|error| with_span(error, *span))?;
+
+                for jump in next {
+                    self.patch_instruction(jump, self.instruction_counter);
+                }
+            }
+            Statement::If(condition) => {
+                let next = self.compile_condition(condition, false)?;
+
+                self.open_block(BlockType::If {
+                    next,
+                    has_else: false,
+                });
+            }
+            Statement::ElseIf(condition) => self.compile_else_if(condition)?,
+            Statement::Else => self.compile_else()?,
+            Statement::EndIf => {
+                let block = self.close_block("IF", "ENDIF")?;
+
+                let BlockType::If { next, .. } = block.block_type else {
+                    unreachable!();
+                };
+
+                for jump in next.into_iter().chain(block.exits) {
+                    self.patch_instruction(jump, self.instruction_counter);
+                }
+            }
+            Statement::Goto(target) => {
+                self.add_instruction(0x40, 0)?;
+                self.add_jump_target(&[self.instruction_counter - 1], *target);
+            }
+            Statement::Gosub(target) => {
+                self.add_instruction(0x45, 0)?;
+                self.add_jump_target(&[self.instruction_counter - 1], *target);
+            }
+            Statement::Return => self.add_instruction(0x46, 0)?,
+            Statement::Let { target, value } => self.compile_let(target, value)?,
+            Statement::Dim { name, size } => {
+                // DIM on a string variable sets the capacity of its buffer
+                let is_string = name.name.ends_with('$');
+                let is_defined = if is_string {
+                    self.find_symbol(&name.name, TableEntryType::StringVariable)
+                        .is_some()
+                } else {
+                    self.find_symbol(&name.name, TableEntryType::Array)
+                        .is_some()
+                        || self
+                            .find_symbol(&name.name, TableEntryType::Variable)
+                            .is_some()
+                };
+
+                if is_defined {
+                    bail!(SyntaxError::new(
+                        format!("{} is already defined", name.name),
+                        name.span
+                    ));
+                }
+
+                if is_string {
+                    self.create_string_variable(name.name.clone(), *size as u32)?;
+                } else {
+                    self.create_array(name.name.clone(), *size as u32)?;
+                }
+            }
+            Statement::For {
+                counter,
+                start,
+                limit,
+                step,
+            } => self.compile_for(&counter.name, counter.span, start, limit, step.as_ref())?,
+            Statement::Next(name) => {
+                let block = self.close_block("FOR", "NEXT")?;
+
+                let BlockType::For {
+                    variable,
+                    counter,
+                    step,
+                    top,
+                } = block.block_type
+                else {
+                    unreachable!();
+                };
+
+                if let Some(name) = name.as_ref().filter(|x| x.name != variable) {
+                    bail!(SyntaxError::new(
+                        format!(
+                            "NEXT {} does not match FOR {} on line {}",
+                            name.name, variable, block.span.line
+                        ),
+                        name.span
+                    ));
+                }
+
+                // increment counter and jump back to the limit check
+                self.add_instruction(0x20, counter)?;
+                self.add_instruction(0x30, step)?;
+                self.add_instruction(0x21, counter)?;
+                self.add_instruction(0x40, top)?;
+
+                for exit in block.exits {
+                    self.patch_instruction(exit, self.instruction_counter);
+                }
+            }
+            Statement::While(condition) => {
+                let top = self.instruction_counter;
+                let exits = self.compile_condition(condition, false)?;
+
+                self.open_block(BlockType::While { top });
+                self.current_block().unwrap().exits.extend(exits);
+            }
+            Statement::Wend => {
+                let block = self.close_block("WHILE", "WEND")?;
+
+                let BlockType::While { top } = block.block_type else {
+                    unreachable!();
+                };
+
+                // check the condition again
+                self.add_instruction(0x40, top)?;
+
+                for exit in block.exits {
+                    self.patch_instruction(exit, self.instruction_counter);
+                }
+            }
+            Statement::Do => {
+                let top = self.instruction_counter;
+                self.open_block(BlockType::Do { top });
+            }
+            Statement::Loop(condition) => {
+                let block = self.close_block("DO", "LOOP")?;
+
+                let BlockType::Do { top } = block.block_type else {
+                    unreachable!();
+                };
+
+                let jumps = match condition {
+                    Some(LoopCondition::Until(condition)) => {
+                        self.compile_condition(condition, false)?
+                    }
+                    Some(LoopCondition::While(condition)) => {
+                        self.compile_condition(condition, true)?
+                    }
+                    None => {
+                        self.add_instruction(0x40, top)?;
+                        vec![]
+                    }
+                };
+
+                for jump in jumps {
+                    self.patch_instruction(jump, top);
+                }
+
+                for exit in block.exits {
+                    self.patch_instruction(exit, self.instruction_counter);
+                }
+            }
+            Statement::Exit => {
+                // jump past the end of the innermost FOR, WHILE or DO
+                self.add_instruction(0x40, 0)?;
+                let exit = self.instruction_counter - 1;
+
+                match self.innermost_loop() {
+                    Some(block) => block.exits.push(exit),
+                    None => bail!("EXIT outside of a loop"),
+                }
+            }
+            Statement::End => self.add_instruction(0x43, 0)?,
+            Statement::Invalid(_) => self.recover_block(statement),
+        }
+
+        Ok(())
+    }
+
+    // keeps blocks balanced after a statement that opens or closes one fails,
+    // so the rest of the program does not report a second error for it
+    pub(super) fn recover_block(&mut self, statement: &Statement) {
+        let placeholder_for = |variable: String| BlockType::For {
+            variable,
+            counter: 0,
+            step: 0,
+            top: 0,
+        };
+        let placeholder_if = || BlockType::If {
+            next: vec![],
+            has_else: false,
+        };
+
+        let block_type = match statement {
+            Statement::For { counter, .. } => placeholder_for(counter.name.clone()),
+            Statement::If(_) => placeholder_if(),
+            Statement::While(_) => BlockType::While { top: 0 },
+            Statement::Invalid(tokens) => match tokens[0].text.to_uppercase().as_str() {
+                "FOR" => placeholder_for(tokens.get(1).map_or(String::new(), |x| x.text.clone())),
+                "IF" if tokens
+                    .last()
+                    .is_some_and(|x| x.text.to_uppercase() == "THEN") =>
+                {
+                    placeholder_if()
+                }
+                "WHILE" => BlockType::While { top: 0 },
+                "DO" => BlockType::Do { top: 0 },
+                closer => {
+                    // a broken closer still closes its block
+                    if self
+                        .blocks
+                        .last()
+                        .is_some_and(|x| x.block_type.closer() == closer)
+                    {
+                        self.blocks.pop();
+                    }
+
+                    return;
+                }
+            },
+            _ => return,
+        };
+
+        self.open_block(block_type);
+    }
+
+    fn compile_input(&mut self, target: &Expression) -> Result<()> {
+        if let ExpressionKind::Element { array, index } = &target.kind {
+            let array = self.find_array(array, target)?;
+
+            // read into a temporary, then copy into the element
+            let temporary = self.allocate_temporary()?;
+            self.add_instruction(0x10, temporary)?;
+            self.compile_index(index)?;
+            self.add_instruction(0x20, temporary)?;
+            self.add_instruction(0x23, array)?;
+            self.release_temporary(temporary);
+
+            return Ok(());
+        }
+
+        let ExpressionKind::Variable(name) = &target.kind else {
+            unreachable!();
+        };
+
+        if name.ends_with('$') {
+            let table_entry =
+                self.find_or_create_string(name.clone(), TableEntryType::StringVariable)?;
+
+            self.add_instruction(0x14, table_entry.location)?;
+
+            return Ok(());
+        }
+
+        let location = self.compile_expression(target)?;
+
+        self.add_instruction(0x10, location)?;
+
+        Ok(())
+    }
+
+    fn compile_print(&mut self, expression: &Expression) -> Result<()> {
+        let string = match &expression.kind {
+            ExpressionKind::String(literal) => Some((literal, TableEntryType::StringConstant)),
+            ExpressionKind::Variable(name) if name.ends_with('$') => {
+                Some((name, TableEntryType::StringVariable))
+            }
+            _ => None,
+        };
+
+        if let Some((symbol, entry_type)) = string {
+            let table_entry = self.find_or_create_string(symbol.clone(), entry_type)?;
+
+            self.add_instruction(0x13, table_entry.location)?;
+
+            // WRITE_STR leaves the line open, so end it like PRINT of a number
+            let newline =
+                self.find_or_create_string("\n".to_string(), TableEntryType::StringConstant)?;
+            self.add_instruction(0x13, newline.location)?;
+
+            return Ok(());
+        }
+
+        let location = self.compile_expression(expression)?;
+
+        self.add_instruction(0x11, location)?;
+        self.release_temporary(location);
+
+        Ok(())
+    }
+
+    fn compile_else_if(&mut self, condition: &Condition) -> Result<()> {
+        let next = match self.current_block() {
+            Some(Block {
+                block_type: BlockType::If { next, has_else },
+                ..
+            }) => {
+                if *has_else {
+                    bail!("ELSEIF cannot come after ELSE");
+                }
+
+                std::mem::take(next)
+            }
+            _ => bail!("ELSEIF without matching IF"),
+        };
+
+        // the previous branch jumps to ENDIF
+        self.add_instruction(0x40, 0)?;
+        self.add_exit();
+
+        for jump in next {
+            self.patch_instruction(jump, self.instruction_counter);
+        }
+
+        let next = self.compile_condition(condition, false)?;
+
+        if let Some(Block {
+            block_type: BlockType::If { next: current, .. },
+            ..
+        }) = self.current_block()
+        {
+            *current = next;
+        }
+
+        Ok(())
+    }
+
+    fn compile_else(&mut self) -> Result<()> {
+        let next = match self.current_block() {
+            Some(Block {
+                block_type: BlockType::If { next, has_else },
+                ..
+            }) => {
+                if *has_else {
+                    bail!("IF cannot have more than one ELSE");
+                }
+
+                *has_else = true;
+                std::mem::take(next)
+            }
+            _ => bail!("ELSE without matching IF"),
+        };
+
+        // the previous branch jumps to ENDIF
+        self.add_instruction(0x40, 0)?;
+        self.add_exit();
+
+        for jump in next {
+            self.patch_instruction(jump, self.instruction_counter);
+        }
+
+        Ok(())
+    }
+
+    fn compile_let(&mut self, target: &Expression, value: &Expression) -> Result<()> {
+        // left-hand array element
+        if let ExpressionKind::Element { array, index } = &target.kind {
+            let array = self.find_array(array, target)?;
+            let result = self.compile_expression(value)?;
+
+            self.compile_index(index)?;
+            self.add_instruction(0x20, result)?;
+            self.add_instruction(0x23, array)?;
+            self.release_temporary(result);
+
+            return Ok(());
+        }
+
+        // left-hand variable, created before the value so it is laid out first
+        let location = self.compile_expression(target)?;
+        let result = self.compile_expression(value)?;
+
+        // load and store result
+        self.add_instruction(0x20, result)?;
+        self.add_instruction(0x21, location)?;
+        self.release_temporary(result);
+
+        Ok(())
+    }
+
+    fn compile_for(
+        &mut self,
+        variable: &str,
+        span: Span,
+        start: &Expression,
+        limit: &Expression,
+        step: Option<&Expression>,
+    ) -> Result<()> {
+        if self.find_symbol(variable, TableEntryType::Array).is_some() {
+            bail!(SyntaxError::new("FOR counter must be a variable", span));
+        }
+
+        let counter = self.find_or_create_symbol(variable.to_string(), TableEntryType::Variable)?;
+
+        // initialise counter
+        let start = self.compile_expression(start)?;
+        self.add_instruction(0x20, start)?;
+        self.add_instruction(0x21, counter.location)?;
+        self.release_temporary(start);
+
+        // limit and step are evaluated once, before the first iteration
+        let limit_location = self.compile_expression(limit)?;
+        let limit = self.copy_unless_constant(limit, limit_location)?;
+
+        let step_location = match step {
+            Some(step) => {
+                let location = self.compile_expression(step)?;
+                self.copy_unless_constant(step, location)?
+            }
+            None => {
+                self.find_or_create_symbol("1".to_string(), TableEntryType::Constant)?
+                    .location
+            }
+        };
+
+        // the direction of the limit check depends on the sign of the step
+        let step_negative = match step {
+            Some(step) => step.to_constant().map(|x| x.is_negative()),
+            None => Some(false),
+        };
+
+        let top = self.instruction_counter;
+
+        self.open_block(BlockType::For {
+            variable: variable.to_string(),
+            counter: counter.location,
+            step: step_location,
+            top,
+        });
+
+        match step_negative {
+            Some(true) => {
+                // exit when counter < limit
+                self.add_instruction(0x20, counter.location)?;
+                self.add_instruction(0x31, limit)?;
+                self.add_instruction(0x41, 0)?;
+                self.add_exit();
+            }
+            Some(false) => {
+                // exit when counter > limit
+                self.add_instruction(0x20, limit)?;
+                self.add_instruction(0x31, counter.location)?;
+                self.add_instruction(0x41, 0)?;
+                self.add_exit();
+            }
+            None => {
+                // check the sign of the step at runtime
+                self.add_instruction(0x20, step_location)?;
+                self.add_instruction(0x41, top + 6)?;
+                self.add_instruction(0x20, limit)?;
+                self.add_instruction(0x31, counter.location)?;
+                self.add_instruction(0x41, 0)?;
+                self.add_exit();
+                self.add_instruction(0x40, top + 9)?;
+                self.add_instruction(0x20, counter.location)?;
+                self.add_instruction(0x31, limit)?;
+                self.add_instruction(0x41, 0)?;
+                self.add_exit();
+            }
+        }
+
+        Ok(())
+    }
+
+    // emits code for an expression and returns the location holding its value
+    pub(super) fn compile_expression(&mut self, expression: &Expression) -> Result<u32> {
+        match &expression.kind {
+            ExpressionKind::Number(number) => Ok(self
+                .find_or_create_symbol(number.to_string(), TableEntryType::Constant)?
+                .location),
+            ExpressionKind::String(_) => bail!(SyntaxError::new(
+                "Strings cannot be used in expressions",
+                expression.span
+            )),
+            ExpressionKind::Variable(name) => {
+                if name.ends_with('$') {
+                    bail!(SyntaxError::new(
+                        "Strings cannot be used in expressions",
+                        expression.span
+                    ));
+                }
+
+                if self.find_symbol(name, TableEntryType::Array).is_some() {
+                    bail!(SyntaxError::new(
+                        format!("Array {name} must be indexed"),
+                        expression.span
+                    ));
+                }
+
+                Ok(self
+                    .find_or_create_symbol(name.clone(), TableEntryType::Variable)?
+                    .location)
+            }
+            ExpressionKind::Element { array, index } => {
+                let array = self.find_array(array, expression)?;
+
+                self.compile_index(index)?;
+
+                // copy element into a temporary
+                let temporary = self.allocate_temporary()?;
+                self.add_instruction(0x22, array)?;
+                self.add_instruction(0x21, temporary)?;
+
+                Ok(temporary)
+            }
+            ExpressionKind::Negate(operand) => {
+                let operand = self.compile_expression(operand)?;
+
+                // negate by subtracting from zero
+                let zero = self.find_or_create_symbol("0".to_string(), TableEntryType::Constant)?;
+                self.add_instruction(0x20, zero.location)?;
+                self.add_instruction(0x31, operand)?;
+                self.release_temporary(operand);
+
+                // store temporary, which can reuse the operand's word
+                let temporary = self.allocate_temporary()?;
+                self.add_instruction(0x21, temporary)?;
+
+                Ok(temporary)
+            }
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let first_operand = self.compile_expression(left)?;
+                let second_operand = self.compile_expression(right)?;
+
+                // load first operand and perform operation
+                self.add_instruction(0x20, first_operand)?;
+                self.add_instruction(operator.operation_code(), second_operand)?;
+                self.release_temporary(first_operand);
+                self.release_temporary(second_operand);
+
+                // store temporary, which can reuse an operand's word
+                let temporary = self.allocate_temporary()?;
+                self.add_instruction(0x21, temporary)?;
+
+                Ok(temporary)
+            }
+        }
+    }
+
+    // returns the location of an array, which must be declared before it is indexed
+    fn find_array(&self, array: &str, element: &Expression) -> Result<u32> {
+        match self.find_symbol(array, TableEntryType::Array) {
+            Some(table_entry) => Ok(table_entry.location),
+            None => bail!(SyntaxError::new(
+                format!("{array} is not an array"),
+                element.span
+            )),
+        }
+    }
+
+    // evaluates an array index and moves it into the index register
+    fn compile_index(&mut self, index: &Expression) -> Result<()> {
+        let location = self.compile_expression(index)?;
+        self.add_instruction(0x24, location)?;
+        self.release_temporary(location);
+
+        Ok(())
+    }
+
+    // copies the value of an expression into a fresh word so later assignments cannot change it,
+    // which also keeps it out of the temporaries reused by later statements
+    fn copy_unless_constant(&mut self, expression: &Expression, location: u32) -> Result<u32> {
+        if expression.to_constant().is_some() {
+            return Ok(location);
+        }
+
+        let data_counter = self.use_data_counter()?;
+        self.add_instruction(0x20, location)?;
+        self.add_instruction(0x21, data_counter)?;
+        self.release_temporary(location);
+
+        Ok(data_counter)
+    }
+
+    // emits code that jumps when the condition is jump_when and otherwise falls through,
+    // returning the jumps to be patched with the target
+    pub(super) fn compile_condition(
+        &mut self,
+        condition: &Condition,
+        jump_when: bool,
+    ) -> Result<Vec<u32>> {
+        match condition {
+            Condition::Or(operands) => self.compile_logical(operands, true, jump_when),
+            Condition::And(operands) => self.compile_logical(operands, false, jump_when),
+            Condition::Not(operand) => self.compile_condition(operand, !jump_when),
+            Condition::Compare {
+                comparison,
+                left,
+                right,
+            } => self.compile_comparison(*comparison, left, right, jump_when),
+        }
+    }
+
+    // short-circuits AND and OR, so later operands are skipped once the result is known
+    fn compile_logical(
+        &mut self,
+        operands: &[Condition],
+        is_or: bool,
+        jump_when: bool,
+    ) -> Result<Vec<u32>> {
+        let mut jumps = vec![];
+
+        // any true operand decides OR, and any false operand decides AND
+        if is_or == jump_when {
+            for operand in operands {
+                jumps.extend(self.compile_condition(operand, jump_when)?);
+            }
+
+            return Ok(jumps);
+        }
+
+        // otherwise every operand but the last one skips past the condition when it decides
+        let mut skips = vec![];
+        for operand in &operands[..operands.len() - 1] {
+            skips.extend(self.compile_condition(operand, !jump_when)?);
+        }
+        jumps.extend(self.compile_condition(&operands[operands.len() - 1], jump_when)?);
+
+        for skip in skips {
+            self.patch_instruction(skip, self.instruction_counter);
+        }
+
+        Ok(jumps)
+    }
+
+    fn compile_comparison(
+        &mut self,
+        comparison: Comparison,
+        left: &Expression,
+        right: &Expression,
+        jump_when: bool,
+    ) -> Result<Vec<u32>> {
+        let location1 = self.compile_expression(left)?;
+        let location2 = self.compile_expression(right)?;
+
+        // jumping when a comparison is false is jumping when its opposite is true
+        let comparison = if jump_when {
+            comparison
+        } else {
+            comparison.negate()
+        };
+
+        let mut jumps = vec![];
+
+        match comparison {
+            Comparison::Equal => {
+                self.add_instruction(0x20, location1)?;
+                self.add_instruction(0x31, location2)?;
+                self.add_instruction(0x42, 0)?;
+                jumps.push(self.instruction_counter - 1);
+            }
+            Comparison::NotEqual => {
+                // skip the jump when the difference is zero
+                self.add_instruction(0x20, location1)?;
+                self.add_instruction(0x31, location2)?;
+                self.add_instruction(0x42, self.instruction_counter + 2)?;
+                self.add_instruction(0x40, 0)?;
+                jumps.push(self.instruction_counter - 1);
+            }
+            Comparison::Less => {
+                self.add_instruction(0x20, location1)?;
+                self.add_instruction(0x31, location2)?;
+                self.add_instruction(0x41, 0)?;
+                jumps.push(self.instruction_counter - 1);
+            }
+            Comparison::Greater => {
+                self.add_instruction(0x20, location2)?;
+                self.add_instruction(0x31, location1)?;
+                self.add_instruction(0x41, 0)?;
+                jumps.push(self.instruction_counter - 1);
+            }
+            Comparison::LessEqual => {
+                self.add_instruction(0x20, location1)?;
+                self.add_instruction(0x31, location2)?;
+                self.add_instruction(0x41, 0)?;
+                self.add_instruction(0x42, 0)?;
+                jumps.extend([self.instruction_counter - 2, self.instruction_counter - 1]);
+            }
+            Comparison::GreaterEqual => {
+                self.add_instruction(0x20, location2)?;
+                self.add_instruction(0x31, location1)?;
+                self.add_instruction(0x41, 0)?;
+                self.add_instruction(0x42, 0)?;
+                jumps.extend([self.instruction_counter - 2, self.instruction_counter - 1]);
+            }
+        }
+
+        self.release_temporary(location1);
+        self.release_temporary(location2);
+
+        Ok(jumps)
+    }
+}
+
+// errors without a span of their own point at span, except running out of memory, which
+// keeps its own code
+fn with_span(error: anyhow::Error, span: Span) -> anyhow::Error {
+    if error.is::<SyntaxError>() || error.is::<MemoryLimitExceeded>() {
+        return error;
+    }
+
+    SyntaxError::new(error.to_string(), span).into()
+}
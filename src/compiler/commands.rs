@@ -1,216 +1,325 @@
-use super::{table_entry::TableEntryType, Compiler};
+use super::{
+    ast::{ExpressionKind, Identifier, LoopCondition, Statement},
+    lexer::{Span, SyntaxError, Token},
+    parser::{
+        is_keyword, parse_condition, parse_expression, parse_identifier, parse_label,
+        parse_statement, MAX_NESTING,
+    },
+};
+use crate::word::Word;
 use anyhow::{bail, Result};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 
-type Command = fn(&mut Compiler, &[String]) -> Result<()>;
+// parses the arguments of a command, where span is the span of the command itself
+type Command = fn(&[Token], Span) -> Result<Statement>;
 
-const REM: Command = |_, _| Ok(());
+fn ends_with_then(args: &[Token]) -> bool {
+    args.last().is_some_and(|x| is_keyword(x, "THEN"))
+}
 
-const INPUT: Command = |compiler, args| {
-    if args.len() != 1 {
-        bail!("INPUT command takes one argument");
+fn no_arguments(args: &[Token], command: &str, statement: Statement) -> Result<Statement> {
+    if let Some(arg) = args.first() {
+        bail!(SyntaxError::new(
+            format!("{command} command takes no arguments"),
+            arg.span
+        ));
     }
 
-    let symbol = compiler.to_symbol(args[0].to_owned())?;
+    Ok(statement)
+}
+
+const REM: Command = |_, _| Ok(Statement::Rem);
 
-    if symbol.1 == TableEntryType::Constant {
-        bail!("Cannot read into constant");
+const INPUT: Command = |args, span| {
+    if args.is_empty() {
+        bail!(SyntaxError::new(
+            "INPUT command takes one argument",
+            span.after()
+        ));
     }
 
-    let table_entry = compiler.find_or_create_symbol(symbol.0, TableEntryType::Variable);
+    let target = parse_expression(args, span.after())?;
 
-    compiler.add_instruction(0x10, table_entry.location);
+    match target.kind {
+        ExpressionKind::Variable(_) | ExpressionKind::Element { .. } => {
+            Ok(Statement::Input(target))
+        }
+        ExpressionKind::Number(_) | ExpressionKind::String(_) => {
+            bail!(SyntaxError::new("Cannot read into constant", target.span))
+        }
+        _ => bail!(SyntaxError::new(
+            "INPUT command takes one argument",
+            target.span
+        )),
+    }
+};
+
+const PRINT: Command = |args, span| {
+    if args.is_empty() {
+        bail!(SyntaxError::new(
+            "PRINT command takes one argument",
+            span.after()
+        ));
+    }
 
-    Ok(())
+    Ok(Statement::Print(parse_expression(args, span.after())?))
 };
 
-const PRINT: Command = |compiler, args| {
-    if args.len() != 1 {
-        bail!("PRINT command takes one argument");
+const IF: Command = |args, span| {
+    // block IF, closed by ENDIF
+    if ends_with_then(args) {
+        let then = &args[args.len() - 1];
+        let condition = parse_condition(&args[..args.len() - 1], then.span)?;
+
+        return Ok(Statement::If(condition));
     }
 
-    let symbol = compiler.to_symbol(args[0].to_owned())?;
+    // inline IF, where any command can follow THEN
+    if let Some(then) = args.iter().position(|x| is_keyword(x, "THEN")) {
+        // every other THEN belongs to an IF nested inside this one
+        if args.iter().filter(|x| is_keyword(x, "THEN")).count() > MAX_NESTING {
+            bail!(SyntaxError::new("IF is nested too deeply", span));
+        }
 
-    let table_entry = compiler.find_or_create_symbol(symbol.0, symbol.1);
+        let condition = parse_condition(&args[..then], args[then].span)?;
+        let tokens = &args[then + 1..];
+
+        // IF ... THEN 50 is short for IF ... THEN GOTO 50
+        let is_line_number = Word::parse_decimal(&tokens[0].text).is_ok();
+        let statement = if tokens.len() == 1 && is_line_number {
+            Statement::Goto(parse_label(tokens, args[then].span, "GOTO")?)
+        } else {
+            parse_statement(tokens)?
+        };
+
+        if statement.is_block() {
+            bail!(SyntaxError::new(
+                format!("{} cannot follow THEN on the same line", tokens[0].text),
+                tokens[0].span
+            ));
+        }
 
-    compiler.add_instruction(0x11, table_entry.location);
+        return Ok(Statement::IfThen {
+            condition,
+            statement: Box::new(statement),
+            span: tokens[0].span,
+        });
+    }
 
-    Ok(())
-};
+    if args.len() < 3 {
+        bail!(SyntaxError::new("Failed to parse IF...GOTO command", span));
+    }
+
+    let goto = &args[args.len() - 2];
 
-const IF: Command = |compiler, args| {
-    if args.len() != 5 {
-        bail!("IF...GOTO command takes 4 arguments");
+    if !is_keyword(goto, "GOTO") {
+        bail!(SyntaxError::new("IF command is missing GOTO or THEN", span));
     }
 
-    let symbol1 = compiler.to_symbol(args[0].to_owned())?;
-    let symbol2 = compiler.to_symbol(args[2].to_owned())?;
-    let symbol3 = compiler.to_symbol(args[4].to_owned())?;
+    Ok(Statement::IfGoto {
+        condition: parse_condition(&args[..args.len() - 2], goto.span)?,
+        target: parse_label(&args[args.len() - 1..], goto.span, "GOTO")?,
+    })
+};
 
-    if symbol3.1 != TableEntryType::Constant {
-        bail!("Cannot GOTO a variable");
+const ELSEIF: Command = |args, span| {
+    if !ends_with_then(args) {
+        bail!(SyntaxError::new("ELSEIF command is missing THEN", span));
     }
 
-    let table_entry1 = compiler.find_or_create_symbol(symbol1.0, symbol1.1);
-    let table_entry2 = compiler.find_or_create_symbol(symbol2.0, symbol2.1);
+    let then = &args[args.len() - 1];
 
-    let mut needs_flag = false;
-    let goto_pos = match compiler.find_line_number(symbol3.0) {
-        Some(goto_pos) => goto_pos.location,
-        None => {
-            needs_flag = true;
-            0
-        }
+    Ok(Statement::ElseIf(parse_condition(
+        &args[..args.len() - 1],
+        then.span,
+    )?))
+};
+
+const ELSE: Command = |args, _| no_arguments(args, "ELSE", Statement::Else);
+
+const ENDIF: Command = |args, _| no_arguments(args, "ENDIF", Statement::EndIf);
+
+const GOTO: Command = |args, span| Ok(Statement::Goto(parse_label(args, span, "GOTO")?));
+
+const GOSUB: Command = |args, span| Ok(Statement::Gosub(parse_label(args, span, "GOSUB")?));
+
+const RETURN: Command = |args, _| no_arguments(args, "RETURN", Statement::Return);
+
+const LET: Command = |args, span| {
+    let equals = match args.iter().position(|x| x.text == "=") {
+        Some(equals) if equals > 0 && equals < args.len() - 1 => equals,
+        _ => bail!(SyntaxError::new("Failed to parse LET command", span)),
     };
 
-    match args[1].as_str() {
-        "==" => {
-            compiler.add_instruction(0x20, table_entry1.location);
-            compiler.add_instruction(0x31, table_entry2.location);
-            compiler.add_instruction(0x42, goto_pos);
-        }
-        "<" => {
-            compiler.add_instruction(0x20, table_entry1.location);
-            compiler.add_instruction(0x31, table_entry2.location);
-            compiler.add_instruction(0x41, goto_pos);
-        }
-        ">" => {
-            compiler.add_instruction(0x20, table_entry2.location);
-            compiler.add_instruction(0x31, table_entry1.location);
-            compiler.add_instruction(0x41, goto_pos);
-        }
-        "<=" => {
-            compiler.add_instruction(0x20, table_entry1.location);
-            compiler.add_instruction(0x31, table_entry2.location);
-            compiler.add_instruction(0x41, goto_pos);
-            compiler.add_instruction(0x42, goto_pos);
-        }
-        ">=" => {
-            compiler.add_instruction(0x20, table_entry2.location);
-            compiler.add_instruction(0x31, table_entry1.location);
-            compiler.add_instruction(0x41, goto_pos);
-            compiler.add_instruction(0x42, goto_pos);
-        }
-        _ => {
-            bail!("Invalid comparison operator");
+    let target = parse_expression(&args[..equals], args[equals].span)?;
+
+    match &target.kind {
+        ExpressionKind::Variable(name) if name.ends_with('$') => bail!(SyntaxError::new(
+            format!("Cannot assign to string {name}"),
+            target.span
+        )),
+        ExpressionKind::Variable(_) | ExpressionKind::Element { .. } => {}
+        ExpressionKind::Number(_) | ExpressionKind::String(_) => {
+            bail!(SyntaxError::new("Cannot assign to constant", target.span))
         }
+        _ => bail!(SyntaxError::new("Failed to parse LET command", target.span)),
     }
 
-    // process add_instruction() before adding flag
-    // otherwise compiler.instruction_counter will be off
-    if needs_flag {
-        compiler.add_flag(symbol3.0);
-    }
+    let value = parse_expression(&args[equals + 1..], args[equals].span.after())?;
 
-    Ok(())
+    Ok(Statement::Let { target, value })
 };
 
-const GOTO: Command = |compiler, args| {
-    if args.len() != 1 {
-        bail!("GOTO command takes 1 argument");
-    }
-
-    let symbol = compiler.to_symbol(args[0].to_owned())?;
-
-    if symbol.1 != TableEntryType::Constant {
-        bail!("Cannot GOTO a variable");
+const DIM: Command = |args, span| {
+    if args.len() != 4 || args[1].text != "(" || args[3].text != ")" {
+        bail!(SyntaxError::new("Failed to parse DIM command", span));
     }
 
-    let mut needs_flag = false;
-    let goto_pos = match compiler.find_line_number(symbol.0) {
-        Some(goto_pos) => goto_pos.location,
-        None => {
-            needs_flag = true;
-            0
+    let name = match parse_identifier(&args[0]) {
+        Ok(name) => name,
+        Err(_) if Word::parse_decimal(&args[0].text).is_ok() || args[0].text.starts_with('"') => {
+            bail!(SyntaxError::new("Cannot DIM a constant", args[0].span))
         }
+        Err(error) => return Err(error),
     };
 
-    compiler.add_instruction(0x40, goto_pos);
+    let size = match Word::parse_decimal(&args[2].text) {
+        Ok(Word::Int(size)) => size,
+        Ok(_) => bail!(SyntaxError::new(
+            "Array size must be an integer",
+            args[2].span
+        )),
+        Err(_) => bail!(SyntaxError::new(
+            "Array size must be a constant",
+            args[2].span
+        )),
+    };
 
-    if needs_flag {
-        compiler.add_flag(symbol.0);
+    if size < 0 {
+        bail!(SyntaxError::new(
+            "Array size cannot be negative",
+            args[2].span
+        ));
     }
 
-    Ok(())
+    Ok(Statement::Dim { name, size })
 };
 
-const LET: Command = |compiler, args| {
-    if args.len() < 3 {
-        bail!("Failed to parse LET command");
+const FOR: Command = |args, span| {
+    if args.len() < 5 || args[1].text != "=" {
+        bail!(SyntaxError::new("Failed to parse FOR command", span));
     }
 
-    // left-hand variable
-    let symbol = compiler.to_symbol(args[0].to_owned())?;
-
-    let table_entry = compiler.find_or_create_symbol(symbol.0, TableEntryType::Variable);
+    let counter = parse_identifier(&args[0])?;
 
-    let infix = args[2..].to_owned();
+    if counter.name.ends_with('$') {
+        bail!(SyntaxError::new(
+            "FOR counter must be a variable",
+            counter.span
+        ));
+    }
 
-    let postfix = match compiler.infix_to_postfix(infix) {
-        Ok(postfix) => postfix,
-        Err(error) => bail!(error),
+    let to_pos = match args.iter().position(|x| is_keyword(x, "TO")) {
+        Some(to_pos) => to_pos,
+        None => bail!(SyntaxError::new("FOR command is missing TO", span)),
     };
+    let step_pos = args.iter().position(|x| is_keyword(x, "STEP"));
 
-    let mut stack: Vec<u32> = vec![];
-
-    for token in postfix {
-        match compiler.to_symbol(token.clone()) {
-            Ok(symbol) => {
-                let table_entry = compiler.find_or_create_symbol(symbol.0, symbol.1);
-
-                stack.push(table_entry.location);
-            }
-            Err(_) => {
-                let second_operand = stack.pop().unwrap();
-
-                // load first operand
-                compiler.add_instruction(0x20, stack.pop().unwrap());
-
-                let operation = match token.as_str() {
-                    "+" => 0x30,
-                    "-" => 0x31,
-                    "/" => 0x32,
-                    "*" => 0x33,
-                    _ => unreachable!(),
-                };
-
-                // perform operation
-                compiler.add_instruction(operation, second_operand);
-
-                // store temporary
-                let data_counter = compiler.use_data_counter();
-                compiler.add_instruction(0x21, data_counter);
-                stack.push(data_counter);
-            }
-        }
+    if step_pos.is_some_and(|x| x < to_pos) {
+        bail!(SyntaxError::new(
+            "STEP must come after TO",
+            args[step_pos.unwrap()].span
+        ));
     }
 
-    // load and store result
-    compiler.add_instruction(0x20, stack.pop().unwrap());
-    compiler.add_instruction(0x21, table_entry.location);
+    let limit_end = step_pos.unwrap_or(args.len());
+
+    let start = parse_expression(&args[2..to_pos], args[to_pos].span)?;
+    let limit = parse_expression(&args[to_pos + 1..limit_end], args[to_pos].span.after())?;
+    let step = match step_pos {
+        Some(step_pos) => Some(parse_expression(
+            &args[step_pos + 1..],
+            args[step_pos].span.after(),
+        )?),
+        None => None,
+    };
 
-    Ok(())
+    Ok(Statement::For {
+        counter,
+        start,
+        limit,
+        step,
+    })
 };
 
-const END: Command = |compiler, args| {
-    if !args.is_empty() {
-        bail!("PRINT command takes no arguments");
+const NEXT: Command = |args, _| {
+    if args.len() > 1 {
+        bail!(SyntaxError::new(
+            "NEXT command takes at most one argument",
+            args[1].span
+        ));
     }
 
-    compiler.add_instruction(0x43, 0);
+    Ok(Statement::Next(args.first().map(|x| Identifier {
+        name: x.text.clone(),
+        span: x.span,
+    })))
+};
+
+const WHILE: Command = |args, span| Ok(Statement::While(parse_condition(args, span.after())?));
+
+const WEND: Command = |args, _| no_arguments(args, "WEND", Statement::Wend);
+
+const DO: Command = |args, _| no_arguments(args, "DO", Statement::Do);
+
+const LOOP: Command = |args, _| {
+    // LOOP UNTIL repeats while the condition is false, LOOP WHILE while it is true
+    let condition = match args.first() {
+        Some(keyword) if is_keyword(keyword, "UNTIL") => Some(LoopCondition::Until(
+            parse_condition(&args[1..], keyword.span.after())?,
+        )),
+        Some(keyword) if is_keyword(keyword, "WHILE") => Some(LoopCondition::While(
+            parse_condition(&args[1..], keyword.span.after())?,
+        )),
+        Some(keyword) => bail!(SyntaxError::new(
+            "LOOP must be followed by UNTIL or WHILE",
+            keyword.span
+        )),
+        None => None,
+    };
 
-    Ok(())
+    Ok(Statement::Loop(condition))
 };
 
+const EXIT: Command = |args, _| no_arguments(args, "EXIT", Statement::Exit);
+
+const END: Command = |args, _| no_arguments(args, "END", Statement::End);
+
+// words used inside commands that cannot be variable names
+pub const RESERVED_WORDS: [&str; 7] = ["TO", "STEP", "THEN", "UNTIL", "AND", "OR", "NOT"];
+
 lazy_static! {
     pub static ref COMMAND_TABLE: HashMap<String, Command> = HashMap::from([
         ("REM".to_string(), REM),
         ("INPUT".to_string(), INPUT),
         ("PRINT".to_string(), PRINT),
         ("IF".to_string(), IF),
+        ("ELSEIF".to_string(), ELSEIF),
+        ("ELSE".to_string(), ELSE),
+        ("ENDIF".to_string(), ENDIF),
         ("GOTO".to_string(), GOTO),
+        ("GOSUB".to_string(), GOSUB),
+        ("RETURN".to_string(), RETURN),
         ("LET".to_string(), LET),
+        ("DIM".to_string(), DIM),
+        ("FOR".to_string(), FOR),
+        ("NEXT".to_string(), NEXT),
+        ("WHILE".to_string(), WHILE),
+        ("WEND".to_string(), WEND),
+        ("DO".to_string(), DO),
+        ("LOOP".to_string(), LOOP),
+        ("EXIT".to_string(), EXIT),
         ("END".to_string(), END)
     ]);
 }
@@ -0,0 +1,323 @@
+use super::lexer::Span;
+use std::fmt::Write;
+
+// codes group diagnostics by the kind of problem, so tools can match on them
+pub const INVALID_CHARACTER: &str = "E0001";
+pub const INVALID_SYNTAX: &str = "E0002";
+pub const INVALID_STATEMENT: &str = "E0003";
+pub const DUPLICATE_LINE: &str = "E0004";
+pub const UNDEFINED_LINE: &str = "E0005";
+pub const UNCLOSED_BLOCK: &str = "E0006";
+pub const MEMORY_LIMIT: &str = "E0007";
+pub const DIVISION_BY_ZERO: &str = "E0008";
+pub const LINE_ORDER: &str = "W0001";
+
+/// Whether a diagnostic stops the program from compiling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// A problem that stops compilation.
+    Error,
+    /// A likely mistake in a program that still compiles.
+    Warning,
+}
+
+impl Severity {
+    /// The lowercase name, as shown before a diagnostic.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A fix that replaces the text at a span.
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    /// What the fix does, shown as help.
+    pub message: String,
+    /// Text to put in place of the span, which is inserted if the span is empty.
+    pub replacement: String,
+    /// Source to replace.
+    pub span: Span,
+}
+
+/// An error or warning about a part of the source.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// Whether this is an error or a warning.
+    pub severity: Severity,
+    /// Code grouping diagnostics by the kind of problem, such as `E0002`.
+    pub code: &'static str,
+    /// What is wrong.
+    pub message: String,
+    /// Source the diagnostic points at.
+    pub span: Span,
+    /// Extra explanation shown below the source line.
+    pub notes: Vec<String>,
+    /// A fix, if there is an obvious one.
+    pub suggestion: Option<Suggestion>,
+}
+
+impl Diagnostic {
+    /// An error with no notes or suggestion.
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            notes: vec![],
+            suggestion: None,
+        }
+    }
+
+    /// A warning with no notes or suggestion.
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, span)
+        }
+    }
+
+    /// Adds a note.
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Sets the suggested fix.
+    pub fn with_suggestion(
+        mut self,
+        message: impl Into<String>,
+        replacement: impl Into<String>,
+        span: Span,
+    ) -> Diagnostic {
+        self.suggestion = Some(Suggestion {
+            message: message.into(),
+            replacement: replacement.into(),
+            span,
+        });
+        self
+    }
+
+    /// Formats the diagnostic like rustc, quoting the source line with a caret under the
+    /// span:
+    ///
+    /// ```text
+    /// error[E0002]: Missing operator before 3
+    ///  --> program.sim:4:12
+    ///   |
+    /// 4 | 10 PRINT 2 3
+    ///   |            ^
+    /// ```
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let span = self.span;
+        let gutter = " ".repeat(span.line.to_string().len());
+
+        let mut output = String::new();
+        writeln!(
+            output,
+            "{}[{}]: {}",
+            self.severity.name(),
+            self.code,
+            self.message
+        )
+        .unwrap();
+        writeln!(output, "{gutter}--> {}:{}:{}", path, span.line, span.start).unwrap();
+
+        if let Some(line) = span.line.checked_sub(1).and_then(|x| source.lines().nth(x)) {
+            // tabs are one column wide, like in the lexer
+            let line = line.replace('\t', " ");
+            let width = span.end.saturating_sub(span.start).max(1);
+
+            writeln!(output, "{gutter} |").unwrap();
+            writeln!(output, "{} | {}", span.line, line.trim_end()).unwrap();
+            writeln!(
+                output,
+                "{gutter} | {}{}",
+                " ".repeat(span.start.saturating_sub(1)),
+                "^".repeat(width)
+            )
+            .unwrap();
+        }
+
+        for note in &self.notes {
+            writeln!(output, "{gutter} = note: {note}").unwrap();
+        }
+
+        if let Some(suggestion) = &self.suggestion {
+            writeln!(output, "{gutter} = help: {}", suggestion.message).unwrap();
+        }
+
+        output
+    }
+
+    /// Formats the diagnostic as a single line of JSON with `"type":"diagnostic"`, where
+    /// columns count from 1 and `column_end` is exclusive.
+    pub fn to_json(&self, path: &str) -> String {
+        let notes: Vec<String> = self.notes.iter().map(|x| json_string(x)).collect();
+        let suggestion = match &self.suggestion {
+            Some(suggestion) => format!(
+                "{{\"message\":{},\"replacement\":{},\"line\":{},\"column_start\":{},\"column_end\":{}}}",
+                json_string(&suggestion.message),
+                json_string(&suggestion.replacement),
+                suggestion.span.line,
+                suggestion.span.start,
+                suggestion.span.end
+            ),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\"type\":\"diagnostic\",\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"line\":{},\"column_start\":{},\"column_end\":{},\"notes\":[{}],\"suggestion\":{}}}",
+            json_string(self.severity.name()),
+            json_string(self.code),
+            json_string(&self.message),
+            json_string(path),
+            self.span.line,
+            self.span.start,
+            self.span.end,
+            notes.join(","),
+            suggestion
+        )
+    }
+}
+
+// quotes and escapes text as a JSON string
+fn json_string(text: &str) -> String {
+    let mut output = String::from("\"");
+
+    for char in text.chars() {
+        match char {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            char if char.is_control() => write!(output, "\\u{:04x}", char as u32).unwrap(),
+            char => output.push(char),
+        }
+    }
+
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, start: usize, end: usize) -> Span {
+        Span { line, start, end }
+    }
+
+    #[test]
+    fn json_fields() {
+        let diagnostic = Diagnostic::error(
+            UNDEFINED_LINE,
+            "Line number 45 does not exist",
+            span(3, 9, 11),
+        )
+        .with_note("GOTO target 45 is not defined; nearest line is 40")
+        .with_suggestion("did you mean line 40?", "40", span(3, 9, 11));
+
+        assert_eq!(
+            diagnostic.to_json("program.sim"),
+            "{\"type\":\"diagnostic\",\"severity\":\"error\",\"code\":\"E0005\",\"message\":\"Line number 45 does not exist\",\
+             \"file\":\"program.sim\",\"line\":3,\"column_start\":9,\"column_end\":11,\
+             \"notes\":[\"GOTO target 45 is not defined; nearest line is 40\"],\
+             \"suggestion\":{\"message\":\"did you mean line 40?\",\"replacement\":\"40\",\
+             \"line\":3,\"column_start\":9,\"column_end\":11}}"
+        );
+    }
+
+    #[test]
+    fn json_without_notes_or_suggestion() {
+        let diagnostic =
+            Diagnostic::warning(LINE_ORDER, "Line 20 comes after line 30", span(2, 1, 3));
+
+        assert_eq!(
+            diagnostic.to_json("a.sim"),
+            "{\"type\":\"diagnostic\",\"severity\":\"warning\",\"code\":\"W0001\",\"message\":\"Line 20 comes after line 30\",\
+             \"file\":\"a.sim\",\"line\":2,\"column_start\":1,\"column_end\":3,\"notes\":[],\
+             \"suggestion\":null}"
+        );
+    }
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(
+            json_string("say \"hi\" to C:\\dir"),
+            "\"say \\\"hi\\\" to C:\\\\dir\""
+        );
+        assert_eq!(json_string("a\nb\rc\td"), "\"a\\nb\\rc\\td\"");
+        assert_eq!(
+            json_string("\u{1}\u{1f}\u{7f}"),
+            "\"\\u0001\\u001f\\u007f\""
+        );
+        assert_eq!(json_string("é→"), "\"é→\"");
+    }
+
+    #[test]
+    fn render_points_at_the_span() {
+        let diagnostic =
+            Diagnostic::error(INVALID_SYNTAX, "Missing operator before 3", span(4, 12, 13));
+
+        assert_eq!(
+            diagnostic.render("program.sim", "10 REM\n\n\n10 PRINT 2 3\n"),
+            "\
+error[E0002]: Missing operator before 3
+ --> program.sim:4:12
+  |
+4 | 10 PRINT 2 3
+  |            ^
+"
+        );
+    }
+
+    #[test]
+    fn render_notes_and_help() {
+        let source = (1..=12)
+            .map(|x| format!("{}0 REM\n", x))
+            .collect::<String>()
+            + "\tGOTO 45";
+        let diagnostic = Diagnostic::error(
+            UNDEFINED_LINE,
+            "Line number 45 does not exist",
+            span(13, 7, 9),
+        )
+        .with_note("GOTO target 45 is not defined")
+        .with_suggestion("did you mean line 40?", "40", span(13, 7, 9));
+
+        // tabs are one column wide, and the gutter grows with the line number
+        assert_eq!(
+            diagnostic.render("p.sim", &source),
+            "\
+error[E0005]: Line number 45 does not exist
+  --> p.sim:13:7
+   |
+13 |  GOTO 45
+   |       ^^
+   = note: GOTO target 45 is not defined
+   = help: did you mean line 40?
+"
+        );
+    }
+
+    #[test]
+    fn render_past_the_end_of_the_source() {
+        let diagnostic =
+            Diagnostic::error(UNCLOSED_BLOCK, "FOR without matching NEXT", span(3, 1, 1));
+
+        // an empty span still gets a caret, and a missing line gets no snippet
+        assert_eq!(
+            diagnostic.render("p.sim", "10 FOR I = 1 TO 2\n"),
+            "error[E0006]: FOR without matching NEXT\n --> p.sim:3:1\n"
+        );
+        assert!(Diagnostic::error(UNCLOSED_BLOCK, "x", span(1, 4, 4))
+            .render("p.sim", "10 FOR I = 1 TO 2")
+            .ends_with("1 | 10 FOR I = 1 TO 2\n  |    ^\n"));
+    }
+}
@@ -0,0 +1,258 @@
+use super::{
+    ast::{Condition, Expression, ExpressionKind, LoopCondition, Operator, Statement, SyntaxTree},
+    diagnostic,
+    lexer::SyntaxError,
+    Compiler, Diagnostic, OptimizationLevel,
+};
+use crate::word::Word;
+use std::collections::HashMap;
+
+// rewrites expressions before code is generated, working out what is known at compile time
+struct Folder {
+    // variables whose value is known wherever they are read from here on
+    constants: HashMap<String, Word>,
+    simplify: bool,
+}
+
+impl Compiler {
+    // reports division by a literal zero and, when optimizing, folds constant expressions
+    // and replaces variables that only ever hold one literal with that literal
+    pub(super) fn fold(&mut self, tree: &mut SyntaxTree) {
+        let mut assignments = HashMap::new();
+        for line in &tree.lines {
+            count_assignments(&line.statement, &mut assignments);
+        }
+
+        let mut folder = Folder {
+            constants: HashMap::new(),
+            simplify: self.optimization_level > OptimizationLevel::None,
+        };
+
+        // no jump can skip a statement until the first one that can jump
+        let mut straight_line = true;
+
+        for line in &mut tree.lines {
+            straight_line &= matches!(
+                line.statement,
+                Statement::Rem
+                    | Statement::Input(_)
+                    | Statement::Print(_)
+                    | Statement::Let { .. }
+                    | Statement::Dim { .. }
+            );
+
+            if let Err(error) = folder.fold_statement(&mut line.statement) {
+                self.diagnostics.push(Diagnostic::error(
+                    diagnostic::DIVISION_BY_ZERO,
+                    error.message,
+                    error.span,
+                ));
+            }
+
+            if !straight_line || !folder.simplify {
+                continue;
+            }
+
+            // every later statement runs after the only assignment to the variable
+            if let Statement::Let {
+                target:
+                    Expression {
+                        kind: ExpressionKind::Variable(name),
+                        ..
+                    },
+                value,
+            } = &line.statement
+            {
+                if let (Some(1), Some(value)) = (assignments.get(name), value.to_constant()) {
+                    folder.constants.insert(name.clone(), value);
+                }
+            }
+        }
+    }
+}
+
+impl Folder {
+    fn fold_statement(&self, statement: &mut Statement) -> Result<(), SyntaxError> {
+        match statement {
+            Statement::Input(target) => self.fold_target(target)?,
+            Statement::Print(expression) => self.fold_expression(expression)?,
+            Statement::IfGoto { condition, .. }
+            | Statement::If(condition)
+            | Statement::ElseIf(condition)
+            | Statement::While(condition)
+            | Statement::Loop(Some(LoopCondition::Until(condition)))
+            | Statement::Loop(Some(LoopCondition::While(condition))) => {
+                self.fold_condition(condition)?
+            }
+            Statement::IfThen {
+                condition,
+                statement,
+                ..
+            } => {
+                self.fold_condition(condition)?;
+                self.fold_statement(statement)?;
+            }
+            Statement::Let { target, value } => {
+                self.fold_target(target)?;
+                self.fold_expression(value)?;
+            }
+            Statement::For {
+                start, limit, step, ..
+            } => {
+                self.fold_expression(start)?;
+                self.fold_expression(limit)?;
+
+                if let Some(step) = step {
+                    self.fold_expression(step)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // a variable being assigned stays a variable, but the index of an element is folded
+    fn fold_target(&self, target: &mut Expression) -> Result<(), SyntaxError> {
+        if let ExpressionKind::Element { index, .. } = &mut target.kind {
+            self.fold_expression(index)?;
+        }
+
+        Ok(())
+    }
+
+    fn fold_condition(&self, condition: &mut Condition) -> Result<(), SyntaxError> {
+        match condition {
+            Condition::Compare { left, right, .. } => {
+                self.fold_expression(left)?;
+                self.fold_expression(right)?;
+            }
+            Condition::Not(operand) => self.fold_condition(operand)?,
+            Condition::And(operands) | Condition::Or(operands) => {
+                for operand in operands {
+                    self.fold_condition(operand)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fold_expression(&self, expression: &mut Expression) -> Result<(), SyntaxError> {
+        let folded = match &mut expression.kind {
+            ExpressionKind::Variable(name) if self.simplify => self.constants.get(name).copied(),
+            ExpressionKind::Element { index, .. } => {
+                self.fold_expression(index)?;
+                None
+            }
+            ExpressionKind::Negate(operand) => {
+                self.fold_expression(operand)?;
+
+                // negation subtracts from zero, like the generated code
+                operand
+                    .to_constant()
+                    .filter(|_| self.simplify)
+                    .and_then(|x| evaluate(Operator::Subtract, Word::Int(0), x))
+            }
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                self.fold_expression(left)?;
+
+                // only a zero written in the source is an error, so whether a program
+                // compiles does not depend on how much is folded
+                if matches!(operator, Operator::Divide | Operator::Modulus)
+                    && right.to_constant().is_some_and(|x| x.is_zero())
+                {
+                    let message = match operator {
+                        Operator::Divide => "Attempt to divide by zero",
+                        _ => "Attempt to modulo by zero",
+                    };
+                    return Err(SyntaxError::new(message, right.span));
+                }
+
+                self.fold_expression(right)?;
+
+                if !self.simplify {
+                    return Ok(());
+                }
+
+                match (left.to_constant(), right.to_constant()) {
+                    (Some(x), Some(y)) => evaluate(*operator, x, y),
+                    _ => {
+                        if let Some(simplified) = simplify(*operator, left, right) {
+                            *expression = simplified;
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(number) = folded {
+            expression.kind = ExpressionKind::Number(number);
+        }
+
+        Ok(())
+    }
+}
+
+// the value of an operation on two constants, unless it would fail or not be a finite
+// number, which is left for the Simpletron to report
+fn evaluate(operator: Operator, x: Word, y: Word) -> Option<Word> {
+    let result = match operator {
+        Operator::Add => x.try_add(y),
+        Operator::Subtract => x.try_subtract(y),
+        Operator::Multiply => x.try_multiply(y),
+        Operator::Divide => x.try_divide(y),
+        Operator::Modulus => x.try_modulus(y),
+        Operator::Exponentiate => x.try_exponentiate(y),
+    };
+
+    result.ok().filter(|x| x.as_float().is_finite())
+}
+
+// X + 0, X - 0, X * 1 and X / 1 are X, but X * 0 is left alone, since working out X could
+// fail, and a real X makes the product a real zero
+fn simplify(operator: Operator, left: &Expression, right: &Expression) -> Option<Expression> {
+    let zero = Some(Word::Int(0));
+    let one = Some(Word::Int(1));
+
+    match operator {
+        Operator::Add if right.to_constant() == zero => Some(left.clone()),
+        Operator::Add if left.to_constant() == zero => Some(right.clone()),
+        Operator::Subtract if right.to_constant() == zero => Some(left.clone()),
+        Operator::Multiply if right.to_constant() == one => Some(left.clone()),
+        Operator::Multiply if left.to_constant() == one => Some(right.clone()),
+        Operator::Divide if right.to_constant() == one => Some(left.clone()),
+        _ => None,
+    }
+}
+
+// counts the statements that can change each variable
+fn count_assignments(statement: &Statement, assignments: &mut HashMap<String, usize>) {
+    let name = match statement {
+        Statement::Input(Expression {
+            kind: ExpressionKind::Variable(name),
+            ..
+        })
+        | Statement::Let {
+            target:
+                Expression {
+                    kind: ExpressionKind::Variable(name),
+                    ..
+                },
+            ..
+        } => name,
+        Statement::For { counter, .. } => &counter.name,
+        Statement::IfThen { statement, .. } => {
+            return count_assignments(statement, assignments);
+        }
+        _ => return,
+    };
+
+    *assignments.entry(name.clone()).or_insert(0) += 1;
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+
+// operators made of two characters, which are matched before single characters
+const DOUBLE_OPERATORS: [&str; 5] = ["==", "!=", "<>", "<=", ">="];
+const SINGLE_OPERATORS: &str = "=<>+-*/%^()";
+
+/// Position of a token in the source, where columns count characters from 1 and end is
+/// exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Line of the source, counting from 1.
+    pub line: usize,
+    /// First column.
+    pub start: usize,
+    /// Column after the last one.
+    pub end: usize,
+}
+
+impl Span {
+    // a span covering both spans, which must be on the same line
+    pub(crate) fn to(self, other: Span) -> Span {
+        Span {
+            line: self.line,
+            start: self.start,
+            end: other.end,
+        }
+    }
+
+    // the column just after the span, used for something missing at the end of a line
+    pub(crate) fn after(self) -> Span {
+        Span {
+            line: self.line,
+            start: self.end,
+            end: self.end + 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub text: String,
+    pub span: Span,
+}
+
+// an error that points at the part of a line that caused it
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl SyntaxError {
+    pub fn new(message: impl Into<String>, span: Span) -> SyntaxError {
+        SyntaxError {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+// splits a line into tokens, dropping whitespace and comments that start with '
+pub fn tokenize(source: &str, line: usize) -> Result<Vec<Token>, SyntaxError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let char = chars[i];
+
+        if char.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if char == '\'' {
+            break;
+        }
+
+        if char == '"' {
+            // string literals keep their quotes
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+
+            if i == chars.len() {
+                let span = Span {
+                    line,
+                    start: start + 1,
+                    end: i + 1,
+                };
+                return Err(SyntaxError::new("Unterminated string", span));
+            }
+
+            i += 1;
+        } else if char.is_ascii_digit() || (char == '.' && next_is_digit(&chars, i)) {
+            i = number_end(&chars, i);
+        } else if char.is_alphabetic() {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            // string variables end in $
+            if i < chars.len() && chars[i] == '$' {
+                i += 1;
+            }
+        } else if i + 1 < chars.len()
+            && DOUBLE_OPERATORS.contains(&chars[i..i + 2].iter().collect::<String>().as_str())
+        {
+            i += 2;
+        } else if SINGLE_OPERATORS.contains(char) {
+            i += 1;
+        } else {
+            let span = Span {
+                line,
+                start: start + 1,
+                end: start + 2,
+            };
+            return Err(SyntaxError::new(
+                format!("Unexpected character {char}"),
+                span,
+            ));
+        }
+
+        tokens.push(Token {
+            text: chars[start..i].iter().collect(),
+            span: Span {
+                line,
+                start: start + 1,
+                end: i + 1,
+            },
+        });
+
+        // the text of a REM statement is not tokenized
+        if tokens.len() <= 2 && tokens[tokens.len() - 1].text.to_uppercase() == "REM" {
+            break;
+        }
+    }
+
+    // lines holding only REM without a line number are comments too
+    if tokens
+        .first()
+        .is_some_and(|x| x.text.to_uppercase() == "REM")
+    {
+        tokens.clear();
+    }
+
+    Ok(tokens)
+}
+
+fn next_is_digit(chars: &[char], i: usize) -> bool {
+    chars.get(i + 1).is_some_and(|x| x.is_ascii_digit())
+}
+
+// returns the index after a number such as 12, 1.5 or 2.5e-3 starting at i
+fn number_end(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+
+    if i < chars.len() && chars[i] == '.' {
+        i += 1;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+
+    // only take an exponent if digits follow it
+    if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+        let mut j = i + 1;
+        if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+            j += 1;
+        }
+
+        if j < chars.len() && chars[j].is_ascii_digit() {
+            i = j;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(source: &str) -> Vec<String> {
+        tokenize(source, 1)
+            .unwrap()
+            .into_iter()
+            .map(|x| x.text)
+            .collect()
+    }
+
+    #[test]
+    fn spans_count_characters_from_one() {
+        let tokens = tokenize("10 LET Total = 5", 7).unwrap();
+        let spans: Vec<(usize, usize, usize)> = tokens
+            .iter()
+            .map(|x| (x.span.line, x.span.start, x.span.end))
+            .collect();
+
+        assert_eq!(
+            spans,
+            [(7, 1, 3), (7, 4, 7), (7, 8, 13), (7, 14, 15), (7, 16, 17)]
+        );
+
+        // characters, not bytes, and a tab is one column
+        let tokens = tokenize("\"é\"\tX", 1).unwrap();
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (5, 6));
+    }
+
+    #[test]
+    fn spacing_is_free() {
+        assert_eq!(
+            texts("10 IF(A+1)*2>=B THEN PRINT-X"),
+            ["10", "IF", "(", "A", "+", "1", ")", "*", "2", ">=", "B", "THEN", "PRINT", "-", "X"]
+        );
+        assert_eq!(
+            texts("  10   LET   X  =  1  "),
+            ["10", "LET", "X", "=", "1"]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            texts("== != <> <= >= = < > + - * / % ^ ( )"),
+            ["==", "!=", "<>", "<=", ">=", "=", "<", ">", "+", "-", "*", "/", "%", "^", "(", ")"]
+        );
+        assert_eq!(texts("A<=-1"), ["A", "<=", "-", "1"]);
+        assert_eq!(texts("A=<B"), ["A", "=", "<", "B"]);
+    }
+
+    #[test]
+    fn names_strings_and_numbers() {
+        assert_eq!(texts("N$ first_name X2"), ["N$", "first_name", "X2"]);
+        assert_eq!(
+            texts("PRINT \"Hello, World! 'not a comment'\""),
+            ["PRINT", "\"Hello, World! 'not a comment'\""]
+        );
+        assert_eq!(
+            texts("12 1.5 .5 2.5e-3 1E6 3."),
+            ["12", "1.5", ".5", "2.5e-3", "1E6", "3."]
+        );
+
+        // an exponent needs digits, so 2E is a number followed by a name
+        assert_eq!(texts("2E X"), ["2", "E", "X"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(texts("10 PRINT X ' show X"), ["10", "PRINT", "X"]);
+        assert_eq!(texts("10 REM \"unterminated & odd"), ["10", "REM"]);
+        assert!(texts("REM a comment without a line number").is_empty());
+        assert!(texts("' a comment").is_empty());
+        assert!(texts("   ").is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let error = tokenize("10 PRINT \"Hello", 3).unwrap_err();
+        assert_eq!(error.message, "Unterminated string");
+        assert_eq!(
+            error.span,
+            Span {
+                line: 3,
+                start: 10,
+                end: 16
+            }
+        );
+
+        let error = tokenize("10 LET X = 1 & 2", 1).unwrap_err();
+        assert_eq!(error.message, "Unexpected character &");
+        assert_eq!(
+            error.span,
+            Span {
+                line: 1,
+                start: 14,
+                end: 15
+            }
+        );
+    }
+
+    #[test]
+    fn joining_and_following_spans() {
+        let a = Span {
+            line: 2,
+            start: 4,
+            end: 6,
+        };
+        let b = Span {
+            line: 2,
+            start: 9,
+            end: 12,
+        };
+
+        assert_eq!(
+            a.to(b),
+            Span {
+                line: 2,
+                start: 4,
+                end: 12
+            }
+        );
+        assert_eq!(
+            b.after(),
+            Span {
+                line: 2,
+                start: 12,
+                end: 13
+            }
+        );
+    }
+}
@@ -1,301 +1,584 @@
+//! The Simple compiler, which turns source into an SML [`Program`].
+
+mod ast;
+mod block;
+mod codegen;
 mod commands;
+mod diagnostic;
+mod fold;
+mod lexer;
+mod optimizer;
+mod parser;
 mod symbol_table;
 mod table_entry;
 
-use crate::config::{INSTRUCTIONS_SEP, MEMORY};
-use anyhow::{bail, Result};
-use commands::COMMAND_TABLE;
-use std::{
-    collections::HashMap,
-    fs::{File, OpenOptions},
-    io::{prelude::*, BufReader, BufWriter},
-    path::PathBuf,
+use crate::{
+    config::{INSTRUCTIONS_SEP, MEMORY, STRING_CAPACITY},
+    program::Program,
+    word::Word,
 };
+use anyhow::{bail, Result};
+use ast::{Label, Line, Statement, SyntaxTree};
+use block::{Block, BlockType};
+use lexer::SyntaxError;
+use std::{collections::HashSet, fmt};
 use symbol_table::SymbolTable;
 use table_entry::{TableEntry, TableEntryType};
 
+pub use diagnostic::{Diagnostic, Severity, Suggestion};
+pub use lexer::Span;
+pub use optimizer::OptimizationLevel;
+
+/// Compiles Simple programs. A compiler can be reused for several programs.
 pub struct Compiler {
     instruction_counter: u32,
     data_counter: u32,
-    instructions: Vec<i32>,
+    instructions: Vec<Word>,
     symbol_table: SymbolTable,
-    flags: Vec<i32>,
+    // jumps to lines that were not compiled yet when the jump was
+    flags: Vec<Option<Label>>,
+    blocks: Vec<Block>,
+    // command of the statement currently being compiled
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+    // words holding intermediate results, which are only live within a statement
+    temporaries: HashSet<u32>,
+    // temporaries whose value has been used, so the next expression can reuse them
+    free_temporaries: Vec<u32>,
+    // words that reusing temporaries did not have to allocate
+    data_words_saved: u32,
+    optimization_level: OptimizationLevel,
 }
 
 impl Compiler {
+    /// A compiler with empty memory.
     pub fn new() -> Compiler {
         Compiler {
             instruction_counter: 0,
             data_counter: MEMORY - 1,
-            instructions: vec![0; MEMORY as usize],
+            instructions: vec![Word::default(); MEMORY as usize],
             symbol_table: SymbolTable::new(),
-            flags: vec![-1; MEMORY as usize],
+            flags: vec![None; MEMORY as usize],
+            blocks: vec![],
+            span: Span::default(),
+            diagnostics: vec![],
+            temporaries: HashSet::new(),
+            free_temporaries: vec![],
+            data_words_saved: 0,
+            optimization_level: OptimizationLevel::default(),
         }
     }
 
-    pub fn compile(&mut self, in_path: PathBuf, out_path: PathBuf) {
-        let file = match File::open(&in_path) {
-            Ok(file) => file,
-            Err(_) => {
-                println!(
-                    "*** Failed to open file {} for reading ***",
-                    &in_path.to_string_lossy()
-                );
-                return;
-            }
+    /// Compiles the source of a Simple program, returning every error and warning in
+    /// source order if it fails.
+    pub fn compile(&mut self, source: &str) -> Result<Program, Vec<Diagnostic>> {
+        *self = Compiler {
+            optimization_level: self.optimization_level,
+            ..Compiler::new()
         };
 
-        let reader = BufReader::new(file);
+        let mut tree = self.parse(source);
+        self.fold(&mut tree);
+        self.generate(&tree);
 
-        // first pass
-        for (i, line) in reader.lines().enumerate() {
-            let tokens: Vec<String> = line.unwrap().split(' ').map(|x| x.to_string()).collect();
+        // diagnostics are kept in source order
+        self.diagnostics
+            .sort_by_key(|x| (x.span.line, x.span.start));
 
-            if tokens.len() < 2 {
-                println!(
-                    "*** Syntax error on line {}: Incomplete statement ***",
-                    i + 1
-                );
-                return;
-            }
+        if self
+            .diagnostics
+            .iter()
+            .any(|x| x.severity == Severity::Error)
+        {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+
+        if self.optimization_level > OptimizationLevel::None {
+            self.optimize();
+        }
+
+        Ok(Program {
+            words: self.instructions.clone(),
+        })
+    }
 
-            // get line number
-            let line_number: u32 = match tokens[0].parse() {
-                Ok(line_number) => line_number,
-                Err(_) => {
-                    println!(
-                        "*** Syntax error on line {}: Invalid line number ***",
-                        i + 1
-                    );
-                    return;
+    /// Which optimizations later compiles run, which is [`OptimizationLevel::Basic`] by
+    /// default.
+    pub fn set_optimization_level(&mut self, optimization_level: OptimizationLevel) {
+        self.optimization_level = optimization_level;
+    }
+
+    /// Number of instructions in the last successful compile.
+    pub fn instruction_count(&self) -> u32 {
+        self.instruction_counter
+    }
+
+    /// Number of data words the last compile saved by reusing the words that hold
+    /// intermediate results once their values have been used.
+    pub fn data_words_saved(&self) -> u32 {
+        self.data_words_saved
+    }
+
+    /// Warnings from the last successful compile.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    // parses every line, keeping lines that fail to parse as invalid statements
+    fn parse(&mut self, source: &str) -> SyntaxTree {
+        let mut tree = SyntaxTree { lines: vec![] };
+
+        for (i, line) in source.lines().enumerate() {
+            let tokens = match lexer::tokenize(line, i + 1) {
+                Ok(tokens) => tokens,
+                Err(error) => {
+                    self.diagnostics.push(Diagnostic::error(
+                        diagnostic::INVALID_CHARACTER,
+                        error.message,
+                        error.span,
+                    ));
+                    continue;
                 }
             };
 
-            if self
-                .symbol_table
-                .find(line_number as i32, TableEntryType::LineNumber)
-                .is_some()
-            {
-                println!(
-                    "*** Syntax error on line {}: Line number already used ***",
-                    i + 1
+            // skip blank and comment-only lines
+            if tokens.is_empty() {
+                continue;
+            }
+
+            match parser::parse_line(&tokens) {
+                Ok(line) => tree.lines.push(line),
+                Err(error) => {
+                    self.add_error(diagnostic::INVALID_SYNTAX, error, tokens[0].span);
+
+                    // jumps to the line and blocks around it still resolve
+                    if let (Ok(number), Some(command)) = (tokens[0].text.parse(), tokens.get(1)) {
+                        tree.lines.push(Line {
+                            number: Label {
+                                number,
+                                span: tokens[0].span,
+                            },
+                            statement: Statement::Invalid(tokens[1..].to_vec()),
+                            span: command.span,
+                        });
+                    }
+                }
+            }
+        }
+
+        tree
+    }
+
+    // generates code for a parsed program, then resolves jumps to later lines
+    fn generate(&mut self, tree: &SyntaxTree) {
+        // first pass
+        let mut previous: Option<Label> = None;
+
+        for line in &tree.lines {
+            self.span = line.span;
+
+            if self.find_line_number(line.number.number).is_some() {
+                self.diagnostics.push(Diagnostic::error(
+                    diagnostic::DUPLICATE_LINE,
+                    format!("Line number {} already used", line.number.number),
+                    line.number.span,
+                ));
+                continue;
+            }
+
+            if let Some(previous) = previous.filter(|x| x.number > line.number.number) {
+                self.diagnostics.push(
+                    Diagnostic::warning(
+                        diagnostic::LINE_ORDER,
+                        format!(
+                            "Line {} comes after line {}",
+                            line.number.number, previous.number
+                        ),
+                        line.number.span,
+                    )
+                    .with_note("lines run in the order they are written, not by their numbers"),
                 );
-                return;
             }
+            previous = Some(line.number);
 
             // insert line number into symbol table
             self.symbol_table.insert(TableEntry {
-                symbol: line_number as i32,
+                symbol: line.number.number.to_string(),
                 entry_type: TableEntryType::LineNumber,
                 location: self.instruction_counter,
             });
 
-            // find command in uppercase
-            let command = COMMAND_TABLE.get(&tokens[1].to_uppercase()).copied();
+            let depth = self.blocks.len();
+            let result = self.compile_statement(&line.statement);
 
-            match command {
-                // call the command
-                Some(command) => match command(self, &tokens[2..]) {
-                    Ok(()) => {}
-                    Err(error) => {
-                        println!("*** Syntax error on line {}: {} ***", i + 1, error);
-                        return;
-                    }
-                },
-                None => {
-                    println!(
-                        "*** Syntax error on line {}: Invalid symbol {} ***",
-                        i + 1,
-                        tokens[1]
-                    );
-                    return;
-                }
-            }
-
-            // program ran out of memory
+            // program ran out of memory, so later lines cannot be compiled
             if self.data_counter <= self.instruction_counter {
-                println!("*** Memory limit exceeded ***");
+                self.diagnostics.push(Diagnostic::error(
+                    diagnostic::MEMORY_LIMIT,
+                    MemoryLimitExceeded.to_string(),
+                    line.span,
+                ));
                 return;
             }
+
+            // errors inside a statement point at its command unless they carry their own span
+            if let Err(error) = result {
+                let code = if error.is::<MemoryLimitExceeded>() {
+                    diagnostic::MEMORY_LIMIT
+                } else {
+                    diagnostic::INVALID_STATEMENT
+                };
+                self.add_error(code, error, line.span);
+
+                if self.blocks.len() == depth {
+                    self.recover_block(&line.statement);
+                }
+            }
+        }
+
+        // every block must be closed before the end of the program, so suggest closing each
+        // one on a new line after the last one, innermost first so the lines nest
+        let last = tree
+            .lines
+            .iter()
+            .map(|x| x.number.number)
+            .max()
+            .unwrap_or(0);
+        let end = Span {
+            line: tree.lines.last().map_or(1, |x| x.span.line + 1),
+            start: 1,
+            end: 1,
+        };
+
+        for (i, block) in std::mem::take(&mut self.blocks)
+            .into_iter()
+            .rev()
+            .enumerate()
+        {
+            let closer = block.block_type.closer();
+            let number = last.saturating_add(10 * (i as i32 + 1));
+
+            self.diagnostics.push(
+                Diagnostic::error(
+                    diagnostic::UNCLOSED_BLOCK,
+                    format!("{} without matching {}", block.block_type.opener(), closer),
+                    block.span,
+                )
+                .with_suggestion(
+                    format!("add {closer} to close the block"),
+                    format!("{number} {closer}\n"),
+                    end,
+                ),
+            );
         }
 
         // second pass
-        for (i, x) in self.flags.iter().enumerate() {
-            if *x == -1 {
+        let mut undefined: Vec<Span> = vec![];
+
+        for (i, label) in self.flags.clone().into_iter().enumerate() {
+            let Some(label) = label else {
                 continue;
-            }
+            };
 
-            let table_entry = match self.find_line_number(*x) {
+            let table_entry = match self.find_line_number(label.number) {
                 Some(table_entry) => table_entry,
+                // conditions can jump to the same line more than once, but report it once
+                None if undefined.contains(&label.span) => continue,
                 None => {
-                    println!("*** GOTO failed: Line number {x} does not exist ***");
-                    return;
+                    undefined.push(label.span);
+                    self.undefined_line(label, i);
+                    continue;
                 }
             };
 
             // add location to BRANCH operation
-            self.instructions[i] += table_entry.location as i32;
+            self.patch_instruction(i as u32, table_entry.location);
         }
+    }
 
-        match self.write(out_path) {
-            Ok(()) => {}
-            Err(error) => {
-                println!("*** {} ***", error);
-            }
+    // records an error, which points at span unless it carries its own
+    fn add_error(&mut self, code: &'static str, error: anyhow::Error, span: Span) {
+        let span = error.downcast_ref::<SyntaxError>().map_or(span, |x| x.span);
+
+        self.diagnostics
+            .push(Diagnostic::error(code, error.to_string(), span));
+    }
+
+    // reports a jump at index to a line that does not exist, pointing out the lines around it
+    fn undefined_line(&mut self, label: Label, index: usize) {
+        let numbers: Vec<i32> = self
+            .symbol_table
+            .entries(TableEntryType::LineNumber)
+            .filter_map(|x| x.symbol.parse().ok())
+            .collect();
+
+        let before = numbers.iter().filter(|x| **x < label.number).max();
+        let after = numbers.iter().filter(|x| **x > label.number).min();
+
+        let command = match self.instructions[index].as_int() {
+            Ok(x) if x / INSTRUCTIONS_SEP as i32 == 0x45 => "GOSUB",
+            _ => "GOTO",
+        };
+        let nearest = match (before, after) {
+            (Some(before), Some(after)) => format!("nearest lines are {before} and {after}"),
+            (Some(line), None) | (None, Some(line)) => format!("nearest line is {line}"),
+            (None, None) => "the program has no lines".to_string(),
+        };
+
+        let mut diagnostic = Diagnostic::error(
+            diagnostic::UNDEFINED_LINE,
+            format!("Line number {} does not exist", label.number),
+            label.span,
+        )
+        .with_note(format!(
+            "{} target {} is not defined; {}",
+            command, label.number, nearest
+        ));
+
+        // suggest whichever line is closer
+        let closest = before
+            .into_iter()
+            .chain(after)
+            .min_by_key(|x| (label.number - **x).unsigned_abs());
+        if let Some(closest) = closest {
+            diagnostic = diagnostic.with_suggestion(
+                format!("did you mean line {closest}?"),
+                closest.to_string(),
+                label.span,
+            );
         }
 
-        println!("*** Compilation finished successfully! ***");
+        self.diagnostics.push(diagnostic);
     }
 
-    pub fn add_instruction(&mut self, operation_code: u32, operand: u32) {
+    // appends an instruction, failing once the code would run into the data
+    fn add_instruction(&mut self, operation_code: u32, operand: u32) -> Result<()> {
+        if self.instruction_counter >= self.data_counter {
+            bail!(MemoryLimitExceeded);
+        }
+
         self.instructions[self.instruction_counter as usize] =
-            (operation_code * INSTRUCTIONS_SEP + operand) as i32;
+            Word::Int((operation_code * INSTRUCTIONS_SEP + operand) as i32);
         self.instruction_counter += 1;
-    }
 
-    pub fn add_flag(&mut self, symbol: i32) {
-        // assume add_flag() is called after add_instruction(), so subtract 1 from index
-        self.flags[self.instruction_counter as usize - 1] = symbol;
+        Ok(())
     }
 
-    // returns current value of data counter and moves it up
-    pub fn use_data_counter(&mut self) -> u32 {
-        self.data_counter -= 1;
-        self.data_counter + 1
+    fn open_block(&mut self, block_type: BlockType) {
+        self.blocks.push(Block {
+            block_type,
+            span: self.span,
+            exits: vec![],
+        });
     }
 
-    pub fn find_line_number(&self, symbol: i32) -> Option<TableEntry> {
-        self.symbol_table.find(symbol, TableEntryType::LineNumber)
+    // mark the last instruction as a branch out of the innermost block
+    fn add_exit(&mut self) {
+        let index = self.instruction_counter - 1;
+        self.blocks.last_mut().unwrap().exits.push(index);
     }
 
-    pub fn find_or_create_symbol(&mut self, symbol: i32, entry_type: TableEntryType) -> TableEntry {
-        match self.symbol_table.find(symbol, entry_type) {
-            Some(table_entry) => table_entry,
-            None => {
-                let new_table_entry = TableEntry {
-                    symbol,
-                    entry_type,
-                    location: self.data_counter,
-                };
+    fn current_block(&mut self) -> Option<&mut Block> {
+        self.blocks.last_mut()
+    }
 
-                self.symbol_table.insert(new_table_entry.clone());
+    fn innermost_loop(&mut self) -> Option<&mut Block> {
+        self.blocks
+            .iter_mut()
+            .rev()
+            .find(|block| block.block_type.is_loop())
+    }
 
-                // directly set constants
-                if entry_type == TableEntryType::Constant {
-                    self.instructions[self.data_counter as usize] = symbol;
-                }
+    // closes the innermost block, which must have been opened by opener
+    fn close_block(&mut self, opener: &str, closer: &str) -> Result<Block> {
+        match self.blocks.last() {
+            Some(block) if block.block_type.opener() == opener => Ok(self.blocks.pop().unwrap()),
+            Some(block) => bail!(
+                "{} cannot close {} on line {}",
+                closer,
+                block.block_type.opener(),
+                block.span.line
+            ),
+            None => bail!("{} without matching {}", closer, opener),
+        }
+    }
 
-                self.data_counter -= 1;
+    // fill in the operand of an instruction that was emitted before its target was known
+    fn patch_instruction(&mut self, index: u32, operand: u32) {
+        if let Word::Int(instruction) = &mut self.instructions[index as usize] {
+            *instruction += operand as i32;
+        }
+    }
 
-                new_table_entry
+    // points jumps at a line, which is resolved in the second pass if it is not compiled yet
+    fn add_jump_target(&mut self, jumps: &[u32], label: Label) {
+        match self.find_line_number(label.number) {
+            Some(table_entry) => {
+                for jump in jumps {
+                    self.patch_instruction(*jump, table_entry.location);
+                }
+            }
+            None => {
+                for jump in jumps {
+                    self.flags[*jump as usize] = Some(label);
+                }
             }
         }
     }
 
-    // write finished instructions to output file
-    fn write(&self, out_path: PathBuf) -> Result<()> {
-        let file = match OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(out_path.clone())
-        {
-            Ok(file) => file,
-            Err(_) => {
-                bail!(
-                    "Failed to open file {} for writing",
-                    &out_path.to_string_lossy()
-                );
-            }
-        };
+    // returns current value of data counter and moves it up, failing once the data would
+    // run into the code
+    fn use_data_counter(&mut self) -> Result<u32> {
+        if self.data_counter <= self.instruction_counter {
+            bail!(MemoryLimitExceeded);
+        }
+
+        self.data_counter -= 1;
+        Ok(self.data_counter + 1)
+    }
+
+    // returns a word for an intermediate result, reusing one whose value has been used
+    // unless optimization is off
+    fn allocate_temporary(&mut self) -> Result<u32> {
+        if self.optimization_level == OptimizationLevel::None {
+            return self.use_data_counter();
+        }
 
-        let mut writer = BufWriter::new(file);
-
-        match writer.write_all(
-            self.instructions
-                .iter()
-                .map(|x| format!("{x:x}"))
-                .collect::<Vec<String>>()
-                .join("\n")
-                .as_bytes(),
-        ) {
-            Ok(()) => Ok(()),
-            Err(_) => bail!("Failed to write to file"),
+        if let Some(temporary) = self.free_temporaries.pop() {
+            self.data_words_saved += 1;
+            return Ok(temporary);
         }
+
+        let temporary = self.use_data_counter()?;
+        self.temporaries.insert(temporary);
+
+        Ok(temporary)
     }
 
-    pub fn to_symbol(&self, token: String) -> Result<(i32, TableEntryType)> {
-        // check if variable
-        if token.len() != 1 || !token.chars().nth(0).unwrap().is_alphabetic() {
-            // check if constant
-            match token.parse::<i32>() {
-                Ok(number) => Ok((number, TableEntryType::Constant)),
-                Err(_) => bail!("Invalid symbol"),
-            }
-        } else {
-            Ok((
-                token.chars().nth(0).unwrap() as i32,
-                TableEntryType::Variable,
-            ))
+    // marks a temporary as free once the instruction using its value has been emitted,
+    // ignoring locations that are not temporaries
+    fn release_temporary(&mut self, location: u32) {
+        if self.temporaries.contains(&location) && !self.free_temporaries.contains(&location) {
+            self.free_temporaries.push(location);
+        }
+    }
+
+    // reserves length contiguous words in the data area and returns the lowest address
+    fn allocate_block(&mut self, length: u32) -> Result<u32> {
+        if length >= self.data_counter.saturating_sub(self.instruction_counter) {
+            bail!(MemoryLimitExceeded);
         }
+
+        let address = self.data_counter + 1 - length;
+        self.data_counter = address - 1;
+
+        Ok(address)
     }
 
-    pub fn infix_to_postfix(&self, infix: Vec<String>) -> Result<Vec<String>> {
-        // infix to postfix: https://www.geeksforgeeks.org/convert-infix-expression-to-postfix-expression/
-        let mut postfix: Vec<String> = vec![];
-        let mut stack: Vec<String> = vec![];
+    // reserves a contiguous block for an array whose first word holds its length
+    fn create_array(&mut self, symbol: String, size: u32) -> Result<TableEntry> {
+        // like BASIC, DIM A(N) allows indexes 0 to N
+        let length = size + 1;
 
-        // operator precedence table
-        let precedence: HashMap<&str, u8> =
-            HashMap::from([("(", 0), ("+", 1), ("-", 1), ("/", 2), ("*", 2)]);
+        let header = self.allocate_block(length + 1)?;
+        self.instructions[header as usize] = Word::Int(length as i32);
 
-        for token in infix {
-            match self.to_symbol(token.clone()) {
-                Ok(_) => {
-                    postfix.push(token);
-                }
-                Err(_) => match token.as_str() {
-                    "(" => {
-                        stack.push(token);
-                    }
-                    ")" => loop {
-                        let last = match stack.last() {
-                            Some(last) => last,
-                            None => {
-                                bail!("Mismatched brackets");
-                            }
-                        };
-                        if last == "(" {
-                            stack.pop();
-                            break;
-                        }
-
-                        // pop all operators between brackets
-                        postfix.push(stack.pop().unwrap());
-                    },
-                    "+" | "-" | "/" | "*" => {
-                        // pop all operators with higher precedence
-                        while !stack.is_empty()
-                            && precedence.get(stack.last().unwrap().as_str())
-                                >= precedence.get(token.as_str())
-                        {
-                            postfix.push(stack.pop().unwrap());
-                        }
-
-                        stack.push(token);
-                    }
-                    _ => {
-                        bail!("Unexpected token {}", token);
-                    }
-                },
-            }
+        let table_entry = TableEntry {
+            symbol,
+            entry_type: TableEntryType::Array,
+            location: header,
+        };
+
+        self.symbol_table.insert(table_entry.clone());
+
+        Ok(table_entry)
+    }
+
+    // reserves a buffer for a string variable, laid out as capacity, length, then characters
+    fn create_string_variable(&mut self, symbol: String, capacity: u32) -> Result<TableEntry> {
+        let header = self.allocate_block(capacity + 2)?;
+        self.instructions[header as usize] = Word::Int(capacity as i32);
+
+        // READ_STR_BUFFER and WRITE_STR point at the length
+        let table_entry = TableEntry {
+            symbol,
+            entry_type: TableEntryType::StringVariable,
+            location: header + 1,
+        };
+
+        self.symbol_table.insert(table_entry.clone());
+
+        Ok(table_entry)
+    }
+
+    // finds or stores a string literal or string variable
+    fn find_or_create_string(
+        &mut self,
+        symbol: String,
+        entry_type: TableEntryType,
+    ) -> Result<TableEntry> {
+        if let Some(table_entry) = self.symbol_table.find(&symbol, entry_type) {
+            return Ok(table_entry);
+        }
+
+        if entry_type == TableEntryType::StringVariable {
+            return self.create_string_variable(symbol, STRING_CAPACITY);
         }
 
-        while let Some(token) = stack.pop() {
-            postfix.push(token);
+        // literals are stored as length, then characters
+        let chars: Vec<char> = symbol.chars().collect();
+        let location = self.allocate_block(chars.len() as u32 + 1)?;
+
+        self.instructions[location as usize] = Word::Int(chars.len() as i32);
+        for (i, char) in chars.iter().enumerate() {
+            self.instructions[location as usize + i + 1] = Word::Int(*char as i32);
         }
 
-        Ok(postfix)
+        let table_entry = TableEntry {
+            symbol,
+            entry_type,
+            location,
+        };
+
+        self.symbol_table.insert(table_entry.clone());
+
+        Ok(table_entry)
+    }
+
+    fn find_symbol(&self, symbol: &str, entry_type: TableEntryType) -> Option<TableEntry> {
+        self.symbol_table.find(symbol, entry_type)
+    }
+
+    fn find_line_number(&self, symbol: i32) -> Option<TableEntry> {
+        self.symbol_table
+            .find(&symbol.to_string(), TableEntryType::LineNumber)
+    }
+
+    fn find_or_create_symbol(
+        &mut self,
+        symbol: String,
+        entry_type: TableEntryType,
+    ) -> Result<TableEntry> {
+        match self.symbol_table.find(&symbol, entry_type) {
+            Some(table_entry) => Ok(table_entry),
+            None => {
+                let location = self.use_data_counter()?;
+                let new_table_entry = TableEntry {
+                    symbol: symbol.clone(),
+                    entry_type,
+                    location,
+                };
+
+                self.symbol_table.insert(new_table_entry.clone());
+
+                // directly set constants
+                if entry_type == TableEntryType::Constant {
+                    self.instructions[location as usize] = Word::parse_decimal(&symbol).unwrap();
+                }
+
+                Ok(new_table_entry)
+            }
+        }
     }
 }
 
@@ -304,3 +587,93 @@ impl Default for Compiler {
         Self::new()
     }
 }
+
+// the program needs more words than the Simpletron has
+#[derive(Debug)]
+struct MemoryLimitExceeded;
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Memory limit exceeded")
+    }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(source: &str) -> Vec<Diagnostic> {
+        Compiler::new().compile(source).err().unwrap()
+    }
+
+    #[test]
+    fn unclosed_blocks_are_closed_innermost_first() {
+        let source = "10 FOR I = 1 TO 3\n20 WHILE I < 2\n30 PRINT I\n";
+        let fixes: Vec<String> = errors(source)
+            .into_iter()
+            .map(|x| x.suggestion.unwrap().replacement)
+            .collect();
+
+        assert_eq!(fixes, ["50 NEXT\n", "40 WEND\n"]);
+        assert!(Compiler::new()
+            .compile(&format!("{source}40 WEND\n50 NEXT\n"))
+            .is_ok());
+    }
+
+    #[test]
+    fn running_out_of_memory() {
+        let diagnostics = errors("10 DIM A(99999)\n20 END\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, diagnostic::MEMORY_LIMIT);
+
+        // the limit is reached part way through a statement hundreds of instructions long
+        let sum = vec!["A"; 150].join(" + ");
+        let source: String = (1..30).map(|x| format!("{x} LET X = {sum}\n")).collect();
+        let diagnostics = errors(&source);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, diagnostic::MEMORY_LIMIT);
+    }
+
+    #[test]
+    fn errors_are_reported_in_source_order() {
+        let source =
+            "10 FOR I = 1 TO 3\n20 PRINT 2 3\n30 NEXT\n40 GOTO 20\n50 LET X = (1\n60 GOTO 99\n";
+        let found: Vec<(&str, String, usize)> = errors(source)
+            .into_iter()
+            .map(|x| (x.code, x.message, x.span.line))
+            .collect();
+
+        // the broken line inside the FOR leaves the block closed, and jumping to it is fine
+        assert_eq!(
+            found,
+            [
+                (
+                    diagnostic::INVALID_SYNTAX,
+                    "Missing operator before 3".to_string(),
+                    2
+                ),
+                (
+                    diagnostic::INVALID_SYNTAX,
+                    "Mismatched brackets".to_string(),
+                    5
+                ),
+                (
+                    diagnostic::UNDEFINED_LINE,
+                    "Line number 99 does not exist".to_string(),
+                    6
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_block_openers_are_still_closed() {
+        let source = "10 FOR I = 1 TO\n20 PRINT I\n30 NEXT\n40 WHILE X <\n50 WEND\n";
+        let lines: Vec<usize> = errors(source).into_iter().map(|x| x.span.line).collect();
+
+        // no NEXT without FOR or WEND without WHILE
+        assert_eq!(lines, [1, 4]);
+    }
+}
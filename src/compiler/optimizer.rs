@@ -0,0 +1,582 @@
+use super::{table_entry::TableEntryType, Compiler};
+use crate::{config::INSTRUCTIONS_SEP, word::Word};
+use std::collections::HashSet;
+
+// operations whose operand is an address in the code rather than in the data area
+const JUMPS: [u32; 4] = [0x40, 0x41, 0x42, 0x45];
+
+// operations that read the word at their operand
+const READS: [u32; 12] = [
+    0x11, 0x13, 0x20, 0x22, 0x23, 0x24, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35,
+];
+
+// operations that write the word at their operand
+const WRITES: [u32; 4] = [0x10, 0x12, 0x14, 0x21];
+
+/// How hard the compiler works to make programs smaller and faster.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptimizationLevel {
+    /// Code exactly as each statement is written, for teaching and debugging.
+    #[default]
+    None,
+    /// Folds constant expressions, reuses words for intermediate results and removes
+    /// redundant loads and stores within each statement.
+    Basic,
+    /// Also keeps values in the accumulator from one statement to the next and threads
+    /// jumps.
+    Full,
+}
+
+// an instruction being optimized, which remembers where it was emitted
+#[derive(Clone, Copy)]
+struct Instruction {
+    address: u32,
+    operation_code: u32,
+    operand: u32,
+    // whether nothing can be assumed about the accumulator here, because a jump lands
+    // here or, below the full level, a statement starts here
+    is_boundary: bool,
+}
+
+impl Compiler {
+    // removes redundant loads and stores from the emitted code, then moves jumps,
+    // line numbers and flags to where their instructions ended up
+    pub(super) fn optimize(&mut self) {
+        let length = self.instruction_counter;
+        let mut code = self.decode();
+
+        loop {
+            let mut changed = remove_redundant_transfers(&mut code);
+            changed |= self.chain_operations(&mut code);
+            changed |= self.remove_dead_stores(&mut code);
+
+            if self.optimization_level == OptimizationLevel::Full {
+                changed |= thread_jumps(&mut code);
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        // new address of every old address, where a removed instruction maps to the one
+        // that took its place
+        let mut addresses = vec![0; length as usize + 1];
+        let mut next = 0;
+        for (address, new_address) in addresses.iter_mut().enumerate() {
+            while next < code.len() && (code[next].address as usize) < address {
+                next += 1;
+            }
+            *new_address = next as u32;
+        }
+
+        let mut flags = vec![None; self.flags.len()];
+
+        for (i, instruction) in code.iter().enumerate() {
+            let operand =
+                if JUMPS.contains(&instruction.operation_code) && instruction.operand <= length {
+                    addresses[instruction.operand as usize]
+                } else {
+                    instruction.operand
+                };
+
+            self.instructions[i] =
+                Word::Int((instruction.operation_code * INSTRUCTIONS_SEP + operand) as i32);
+            flags[i] = self.flags[instruction.address as usize];
+        }
+
+        for i in code.len()..length as usize {
+            self.instructions[i] = Word::default();
+        }
+
+        for table_entry in self.symbol_table.entries_mut(TableEntryType::LineNumber) {
+            table_entry.location = addresses[table_entry.location as usize];
+        }
+
+        self.flags = flags;
+        self.instruction_counter = code.len() as u32;
+    }
+
+    // splits the emitted code into instructions, marking the boundaries the optimizer
+    // cannot see past
+    fn decode(&self) -> Vec<Instruction> {
+        let mut code: Vec<Instruction> = self.instructions[..self.instruction_counter as usize]
+            .iter()
+            .enumerate()
+            .map(|(address, word)| {
+                let instruction = word.as_int().unwrap_or_default() as u32;
+
+                Instruction {
+                    address: address as u32,
+                    operation_code: instruction / INSTRUCTIONS_SEP,
+                    operand: instruction % INSTRUCTIONS_SEP,
+                    is_boundary: false,
+                }
+            })
+            .collect();
+
+        let mut boundaries = HashSet::new();
+
+        // keep each statement's code to itself unless optimizing fully
+        if self.optimization_level < OptimizationLevel::Full {
+            boundaries.extend(
+                self.symbol_table
+                    .entries(TableEntryType::LineNumber)
+                    .map(|x| x.location),
+            );
+        }
+
+        for instruction in &code {
+            if JUMPS.contains(&instruction.operation_code) {
+                boundaries.insert(instruction.operand);
+            }
+
+            // RETURN comes back to the instruction after a CALL
+            if instruction.operation_code == 0x45 {
+                boundaries.insert(instruction.address + 1);
+            }
+        }
+
+        for instruction in &mut code {
+            instruction.is_boundary = boundaries.contains(&instruction.address);
+        }
+
+        code
+    }
+
+    // STORE t, LOAD a, ADD t becomes ADD a when nothing else reads the temporary t,
+    // and likewise for MULTIPLY, since both are commutative
+    fn chain_operations(&self, code: &mut Vec<Instruction>) -> bool {
+        let mut changed = false;
+        let mut i = 0;
+
+        while i + 2 < code.len() {
+            let (store, load, operation) = (code[i], code[i + 1], code[i + 2]);
+
+            if store.operation_code == 0x21
+                && self.temporaries.contains(&store.operand)
+                && count_uses(code, i) == 1
+                && load.operation_code == 0x20
+                && !load.is_boundary
+                && matches!(operation.operation_code, 0x30 | 0x33)
+                && operation.operand == store.operand
+                && !operation.is_boundary
+            {
+                code[i] = Instruction {
+                    operation_code: operation.operation_code,
+                    operand: load.operand,
+                    ..store
+                };
+                code.drain(i + 1..i + 3);
+                changed = true;
+            }
+
+            i += 1;
+        }
+
+        changed
+    }
+
+    // drops stores to temporaries whose value is never read
+    fn remove_dead_stores(&self, code: &mut Vec<Instruction>) -> bool {
+        let mut changed = false;
+        let mut i = 0;
+
+        while i < code.len() {
+            let instruction = code[i];
+
+            if instruction.operation_code == 0x21
+                && self.temporaries.contains(&instruction.operand)
+                && count_uses(code, i) == 0
+            {
+                code.remove(i);
+                changed = true;
+
+                // jumps to the store now land on the instruction after it
+                if let Some(next) = code.get_mut(i) {
+                    next.is_boundary |= instruction.is_boundary;
+                }
+                continue;
+            }
+
+            i += 1;
+        }
+
+        changed
+    }
+}
+
+// drops a LOAD or STORE of the word the accumulator already holds, such as the reload in
+// STORE t, LOAD t
+fn remove_redundant_transfers(code: &mut Vec<Instruction>) -> bool {
+    // address whose value is in the accumulator
+    let mut known: Option<u32> = None;
+    let length = code.len();
+
+    code.retain(|instruction| {
+        if instruction.is_boundary {
+            known = None;
+        }
+
+        match instruction.operation_code {
+            0x20 | 0x21 if known == Some(instruction.operand) => return false,
+            0x20 | 0x21 => known = Some(instruction.operand),
+            // these leave both the accumulator and the data words alone
+            0x11 | 0x13 | 0x24 | 0x41 | 0x42 | 0x44 => {}
+            _ => known = None,
+        }
+
+        true
+    });
+
+    code.len() != length
+}
+
+// points jumps that land on a BRANCH at where the BRANCH goes, then deletes jumps to the
+// next instruction and code that nothing jumps to or falls into
+fn thread_jumps(code: &mut Vec<Instruction>) -> bool {
+    let mut changed = false;
+
+    for i in 0..code.len() {
+        if !JUMPS.contains(&code[i].operation_code) {
+            continue;
+        }
+
+        let destination = follow_branches(code, code[i].operand);
+        if destination != code[i].operand {
+            code[i].operand = destination;
+            changed = true;
+        }
+    }
+
+    // addresses execution can arrive at other than from the instruction before
+    let mut targets: HashSet<u32> = HashSet::new();
+    for (i, instruction) in code.iter().enumerate() {
+        if JUMPS.contains(&instruction.operation_code) {
+            if let Some(target) = code.get(position(code, instruction.operand)) {
+                targets.insert(target.address);
+            }
+        }
+
+        // RETURN comes back to the instruction after a CALL
+        if instruction.operation_code == 0x45 {
+            if let Some(next) = code.get(i + 1) {
+                targets.insert(next.address);
+            }
+        }
+    }
+
+    let mut kept: Vec<Instruction> = Vec::with_capacity(code.len());
+    // whether the last kept instruction can continue to the next one
+    let mut falls_through = true;
+    // whether the next kept instruction takes the place of a removed boundary
+    let mut is_boundary = false;
+    let mut i = 0;
+
+    while i < code.len() {
+        let instruction = code[i];
+        let is_target = targets.contains(&instruction.address);
+
+        let is_unreachable = !falls_through && !is_target;
+        let is_jump_to_next = matches!(instruction.operation_code, 0x40..=0x42)
+            && position(code, instruction.operand) == i + 1;
+
+        if is_unreachable || is_jump_to_next {
+            // jumps to the removed instruction now land on the next one
+            if let Some(next) = code.get(i + 1).filter(|_| is_target) {
+                targets.insert(next.address);
+            }
+
+            is_boundary |= instruction.is_boundary;
+            changed = true;
+            i += 1;
+            continue;
+        }
+
+        kept.push(Instruction {
+            is_boundary: instruction.is_boundary || is_boundary,
+            ..instruction
+        });
+
+        falls_through = !matches!(instruction.operation_code, 0x40 | 0x43 | 0x46);
+        is_boundary = false;
+        i += 1;
+    }
+
+    *code = kept;
+
+    changed
+}
+
+// where a jump to address ends up after any BRANCH instructions there, or address itself
+// if the branches go round in a loop
+fn follow_branches(code: &[Instruction], address: u32) -> u32 {
+    let mut destination = address;
+
+    for _ in 0..code.len() {
+        match code.get(position(code, destination)) {
+            Some(instruction) if instruction.operation_code == 0x40 => {
+                destination = instruction.operand;
+            }
+            Some(instruction) => return instruction.address,
+            None => return destination,
+        }
+    }
+
+    address
+}
+
+// index of the instruction a jump to address lands on, which is the next one kept if the
+// instruction there was removed
+fn position(code: &[Instruction], address: u32) -> usize {
+    code.partition_point(|x| x.address < address)
+}
+
+// number of instructions reading the value written at index, which a temporary only holds
+// until it is next written, since temporaries are reused but never live across a jump
+fn count_uses(code: &[Instruction], index: usize) -> usize {
+    let address = code[index].operand;
+
+    code[index + 1..]
+        .iter()
+        .filter(|x| x.operand == address)
+        .take_while(|x| !WRITES.contains(&x.operation_code))
+        .filter(|x| READS.contains(&x.operation_code))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OptimizationLevel;
+    use crate::{BufferIo, Compiler, Outcome, Simulator};
+
+    // what a program prints and how it ends at a level, leaving out the address of a
+    // crash, which optimizing moves
+    fn run(source: &str, optimization_level: OptimizationLevel, input: &str) -> String {
+        let mut compiler = Compiler::new();
+        compiler.set_optimization_level(optimization_level);
+        let program = compiler.compile(source).unwrap();
+
+        let mut io = BufferIo::new(input);
+        let mut simulator = Simulator::with_io(&mut io);
+        simulator.set_step_limit(Some(100_000));
+        simulator.load(&program).unwrap();
+
+        let outcome = match simulator.simulate() {
+            Outcome::Crashed { reason, .. } => reason,
+            outcome => format!("{outcome:?}"),
+        };
+        drop(simulator);
+
+        format!("{}{}", io.output(), outcome)
+    }
+
+    fn instruction_count(source: &str, optimization_level: OptimizationLevel) -> u32 {
+        let mut compiler = Compiler::new();
+        compiler.set_optimization_level(optimization_level);
+        compiler.compile(source).unwrap();
+
+        compiler.instruction_count()
+    }
+
+    // checks that optimizing changes the code but not what it does with any of the inputs
+    fn assert_same_at_every_level(source: &str, inputs: &[&str]) {
+        assert!(
+            instruction_count(source, OptimizationLevel::Full)
+                < instruction_count(source, OptimizationLevel::None)
+        );
+
+        assert_same_output(source, inputs);
+    }
+
+    fn assert_same_output(source: &str, inputs: &[&str]) {
+        for input in inputs {
+            let expected = run(source, OptimizationLevel::None, input);
+
+            for optimization_level in [OptimizationLevel::Basic, OptimizationLevel::Full] {
+                assert_eq!(
+                    run(source, optimization_level, input),
+                    expected,
+                    "{optimization_level:?} with input {input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn redundant_transfers_and_dead_stores() {
+        let source = "\
+10 INPUT A
+20 INPUT B
+30 LET C = A + B * 2
+40 LET D = (C - A) * (B + 1) / 2
+50 LET E = A * B + C * D
+55 LET F = A - B * C - A / (B + 3)
+60 PRINT C
+70 PRINT D
+80 PRINT E
+85 PRINT F
+90 END
+";
+
+        assert!(
+            instruction_count(source, OptimizationLevel::Basic)
+                < instruction_count(source, OptimizationLevel::None)
+        );
+        assert_same_at_every_level(source, &["1\n2\n", "-3\n4\n", "0\n0\n", "2.5\n-1\n"]);
+    }
+
+    #[test]
+    fn gosub_return_points() {
+        // line 100 is both called and fallen into, with different values in the accumulator
+        let source = "\
+10 INPUT X
+20 LET Y = X + 1
+30 LET Z = X * 5
+40 GOSUB 100
+50 PRINT Y
+60 PRINT Z
+90 LET Y = 20
+100 LET Y = Y * 3
+110 PRINT Y
+120 IF Y > 50 THEN END
+130 RETURN
+";
+
+        assert_same_at_every_level(source, &["0\n", "2\n", "20\n", "-5\n"]);
+    }
+
+    #[test]
+    fn for_with_step_sign_known_at_runtime() {
+        let source = "\
+10 INPUT A
+20 INPUT B
+30 INPUT S
+40 FOR I = A TO B STEP S
+50 LET J = I * 2
+55 PRINT J
+60 NEXT I
+70 PRINT I
+80 END
+";
+
+        assert_same_at_every_level(
+            source,
+            &[
+                "1\n5\n1\n",
+                "1\n5\n2\n",
+                "5\n1\n-1\n",
+                "5\n1\n1\n",
+                "1\n5\n-1\n",
+                "3\n3\n-2\n",
+            ],
+        );
+    }
+
+    #[test]
+    fn not_equal() {
+        let source = "\
+10 INPUT A
+20 INPUT B
+30 IF A != B THEN PRINT 1
+40 IF A + 1 != B THEN GOTO 60
+50 PRINT 2
+60 IF A != B AND A != 0 THEN PRINT 3
+70 END
+";
+
+        assert_same_at_every_level(source, &["1\n1\n", "1\n2\n", "0\n3\n", "4\n-4\n"]);
+    }
+
+    #[test]
+    fn goto_chains() {
+        let source = "\
+10 INPUT A
+20 GOTO 40
+30 PRINT 1
+40 GOTO 60
+50 PRINT 2
+60 IF A > 0 GOTO 100
+70 IF A == 0 THEN GOTO 120
+80 GOSUB 130
+90 GOTO 140
+100 PRINT 3
+110 GOTO 80
+120 GOTO 100
+130 GOTO 150
+140 END
+150 PRINT A
+160 RETURN
+";
+
+        assert!(
+            instruction_count(source, OptimizationLevel::Full)
+                < instruction_count(source, OptimizationLevel::Basic)
+        );
+        assert_same_at_every_level(source, &["1\n", "0\n", "-1\n"]);
+    }
+
+    #[test]
+    fn less_equal_and_greater_equal_branches() {
+        // branching on b - a < 0 instead of a - b > 0 would overflow where a - b doesn't
+        for comparison in ["==", "<>", "<", ">", "<=", ">="] {
+            let source = format!(
+                "\
+10 INPUT A
+20 INPUT B
+30 IF A {comparison} B THEN GOTO 60
+40 PRINT 1
+50 GOTO 70
+60 PRINT 2
+70 END
+"
+            );
+
+            assert_same_output(
+                &source,
+                &[
+                    "-2147483648\n0\n",
+                    "0\n-2147483648\n",
+                    "1\n2\n",
+                    "2\n1\n",
+                    "2\n2\n",
+                    "-1\n-1\n",
+                    "1.5\n1.25\n",
+                    "0\n-0.5\n",
+                ],
+            );
+        }
+    }
+
+    #[test]
+    fn multiply_by_zero_keeps_what_the_operand_does() {
+        // the operand can crash or hold a real number, which makes the product a real zero
+        let source = "\
+10 INPUT Z
+20 INPUT X
+30 LET R = X * 0
+40 PRINT R
+50 LET R = 0 * (Z * 2000000000)
+60 PRINT R
+70 LET R = (1 / Z) * 0
+80 PRINT R
+90 LET R = (Z ^ -1) * 0
+100 PRINT R
+110 END
+";
+
+        assert_same_at_every_level(source, &["0\n1\n", "2\n2.5\n", "2.5\n-1\n", "0.5\n0\n"]);
+    }
+
+    #[test]
+    fn division_by_folded_zero() {
+        // only a literal zero is rejected when compiling, so this fails when it runs
+        let source = "\
+10 LET X = 1 / (2 - 2)
+20 PRINT X
+30 END
+";
+
+        assert_same_at_every_level(source, &[""]);
+    }
+}
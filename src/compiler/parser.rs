@@ -0,0 +1,833 @@
+use super::{
+    ast::{
+        Comparison, Condition, Expression, ExpressionKind, Identifier, Label, Line, Operator,
+        Statement,
+    },
+    commands::{COMMAND_TABLE, RESERVED_WORDS},
+    lexer::{Span, SyntaxError, Token},
+};
+use crate::word::Word;
+use anyhow::{bail, Result};
+
+// deepest that expressions, conditions and inline IFs can be nested, well within what
+// compiling them can recurse through
+pub const MAX_NESTING: usize = 200;
+
+// parses a line of tokens, which starts with its line number
+pub fn parse_line(tokens: &[Token]) -> Result<Line> {
+    if tokens.len() < 2 {
+        bail!(SyntaxError::new(
+            "Incomplete statement",
+            tokens[0].span.after()
+        ));
+    }
+
+    let number = match tokens[0].text.parse() {
+        Ok(number) => number,
+        Err(_) => bail!(SyntaxError::new("Invalid line number", tokens[0].span)),
+    };
+
+    Ok(Line {
+        number: Label {
+            number,
+            span: tokens[0].span,
+        },
+        statement: parse_statement(&tokens[1..])?,
+        span: tokens[1].span,
+    })
+}
+
+// parses a command and its arguments, which may be nested inside another command
+pub fn parse_statement(tokens: &[Token]) -> Result<Statement> {
+    // find command in uppercase
+    let command = COMMAND_TABLE.get(&tokens[0].text.to_uppercase()).copied();
+
+    match command {
+        Some(command) => command(&tokens[1..], tokens[0].span),
+        None => bail!(SyntaxError::new(
+            format!("Invalid symbol {}", tokens[0].text),
+            tokens[0].span
+        )),
+    }
+}
+
+pub fn is_keyword(token: &Token, keyword: &str) -> bool {
+    token.text.to_uppercase() == keyword
+}
+
+// checks that a token is a name that is not taken by a command
+pub fn parse_identifier(token: &Token) -> Result<Identifier> {
+    if !token.text.starts_with(|x: char| x.is_alphabetic()) {
+        bail!(SyntaxError::new(
+            format!("Invalid symbol {}", token.text),
+            token.span
+        ));
+    }
+
+    let keyword = token.text.trim_end_matches('$').to_uppercase();
+    if COMMAND_TABLE.contains_key(&keyword) || RESERVED_WORDS.contains(&keyword.as_str()) {
+        bail!(SyntaxError::new(
+            format!("{} is a reserved keyword", token.text),
+            token.span
+        ));
+    }
+
+    Ok(Identifier {
+        name: token.text.clone(),
+        span: token.span,
+    })
+}
+
+// parses the single line number taken by GOTO and GOSUB
+pub fn parse_label(args: &[Token], span: Span, command: &str) -> Result<Label> {
+    let [token] = args else {
+        let span = args.get(1).map_or(span.after(), |x| x.span);
+        bail!(SyntaxError::new(
+            format!("{command} command takes 1 argument"),
+            span
+        ));
+    };
+
+    match Word::parse_decimal(&token.text) {
+        Ok(Word::Int(number)) => Ok(Label {
+            number,
+            span: token.span,
+        }),
+        Ok(_) => bail!(SyntaxError::new("Invalid line number", token.span)),
+        Err(_) if token.text.starts_with(|x: char| x.is_alphabetic()) => bail!(SyntaxError::new(
+            format!("Cannot {command} a variable"),
+            token.span
+        )),
+        Err(_) => bail!(SyntaxError::new(
+            format!("Invalid symbol {}", token.text),
+            token.span
+        )),
+    }
+}
+
+// parses an arithmetic expression, where span marks where a missing expression should be
+pub fn parse_expression(tokens: &[Token], span: Span) -> Result<Expression> {
+    parse_nested_expression(tokens, span, 0)
+}
+
+// parses an expression inside a condition nested depth deep
+fn parse_nested_expression(tokens: &[Token], span: Span, depth: usize) -> Result<Expression> {
+    if tokens.is_empty() {
+        bail!(SyntaxError::new("Missing expression", span));
+    }
+
+    let mut parser = ExpressionParser {
+        tokens,
+        position: 0,
+        depth,
+    };
+
+    let expression = parser.parse_sum()?;
+
+    // anything left over did not join the expression
+    if let Some(token) = parser.peek() {
+        let message = if token.text == ")" {
+            "Mismatched brackets".to_string()
+        } else if starts_operand(token) {
+            format!("Missing operator before {}", token.text)
+        } else {
+            format!("Unexpected token {}", token.text)
+        };
+
+        bail!(SyntaxError::new(message, token.span));
+    }
+
+    Ok(expression)
+}
+
+fn starts_operand(token: &Token) -> bool {
+    token.text == "("
+        || token
+            .text
+            .starts_with(|x: char| x.is_alphanumeric() || x == '.' || x == '"')
+}
+
+// recursive descent over the tokens of one expression, from loosest to tightest binding:
+// + and -, then * / and %, then unary minus, then ^ which is right-associative
+struct ExpressionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    // how deep the expression being parsed is nested, counting each operator in a chain
+    // like A + B + C, since later passes walk the expression recursively
+    depth: usize,
+}
+
+impl ExpressionParser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_is(&self, offset: usize, texts: &[&str]) -> bool {
+        self.tokens
+            .get(self.position + offset)
+            .is_some_and(|x| texts.contains(&x.text.as_str()))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // goes one level deeper, failing past the limit instead of overflowing the stack later
+    fn nest(&mut self, span: Span) -> Result<()> {
+        self.depth += 1;
+
+        if self.depth > MAX_NESTING {
+            bail!(SyntaxError::new("Expression is nested too deeply", span));
+        }
+
+        Ok(())
+    }
+
+    // span just after the last token, where a missing operand belongs
+    fn end(&self) -> Span {
+        self.tokens[self.tokens.len() - 1].span.after()
+    }
+
+    fn parse_sum(&mut self) -> Result<Expression> {
+        let depth = self.depth;
+        let mut left = self.parse_product()?;
+
+        while self.peek_is(0, &["+", "-"]) {
+            let token = self.next().unwrap();
+            let operator = match token.text.as_str() {
+                "+" => Operator::Add,
+                _ => Operator::Subtract,
+            };
+            self.nest(token.span)?;
+            let right = self.parse_product()?;
+
+            left = binary(operator, left, right);
+        }
+
+        self.depth = depth;
+
+        Ok(left)
+    }
+
+    fn parse_product(&mut self) -> Result<Expression> {
+        let depth = self.depth;
+        let mut left = self.parse_unary()?;
+
+        while self.peek_is(0, &["*", "/", "%"]) {
+            let token = self.next().unwrap();
+            let operator = match token.text.as_str() {
+                "*" => Operator::Multiply,
+                "/" => Operator::Divide,
+                _ => Operator::Modulus,
+            };
+            self.nest(token.span)?;
+            let right = self.parse_unary()?;
+
+            left = binary(operator, left, right);
+        }
+
+        self.depth = depth;
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression> {
+        // unary plus does nothing
+        if self.peek_is(0, &["+"]) {
+            let plus = self.next().unwrap();
+            self.nest(plus.span)?;
+            let operand = self.parse_unary()?;
+            self.depth -= 1;
+
+            return Ok(operand);
+        }
+
+        if !self.peek_is(0, &["-"]) {
+            return self.parse_power();
+        }
+
+        let minus = self.next().unwrap();
+
+        // a minus before a number is a negative literal, except in -2 ^ 2 which is -(2 ^ 2)
+        let number = self
+            .peek()
+            .and_then(|x| Word::parse_decimal(&format!("-{}", x.text)).ok());
+        if let Some(number) = number {
+            if !self.peek_is(1, &["^"]) {
+                let token = self.next().unwrap();
+                let span = minus.span.to(token.span);
+
+                return Ok(Expression {
+                    kind: ExpressionKind::Number(check_range(number, &token.text, span)?),
+                    span,
+                });
+            }
+        }
+
+        self.nest(minus.span)?;
+        let operand = self.parse_unary()?;
+        self.depth -= 1;
+
+        Ok(Expression {
+            span: minus.span.to(operand.span),
+            kind: ExpressionKind::Negate(Box::new(operand)),
+        })
+    }
+
+    fn parse_power(&mut self) -> Result<Expression> {
+        let base = self.parse_primary()?;
+
+        if !self.peek_is(0, &["^"]) {
+            return Ok(base);
+        }
+
+        let caret = self.next().unwrap();
+
+        // the exponent may itself be negated, as in 2 ^ -1
+        self.nest(caret.span)?;
+        let exponent = self.parse_unary()?;
+        self.depth -= 1;
+
+        Ok(binary(Operator::Exponentiate, base, exponent))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression> {
+        let Some(token) = self.next() else {
+            bail!(SyntaxError::new("Incomplete expression", self.end()));
+        };
+
+        if token.text == "(" {
+            self.nest(token.span)?;
+            let mut inner = self.parse_sum()?;
+            let close = self.close_bracket(&token)?;
+            self.depth -= 1;
+
+            inner.span = token.span.to(close);
+
+            return Ok(inner);
+        }
+
+        if let Some(literal) = token.text.strip_prefix('"') {
+            return Ok(Expression {
+                kind: ExpressionKind::String(literal[..literal.len() - 1].to_string()),
+                span: token.span,
+            });
+        }
+
+        if let Ok(number) = Word::parse_decimal(&token.text) {
+            return Ok(Expression {
+                kind: ExpressionKind::Number(check_range(number, &token.text, token.span)?),
+                span: token.span,
+            });
+        }
+
+        if !starts_operand(&token) {
+            bail!(SyntaxError::new(
+                format!("Missing operand before {}", token.text),
+                token.span
+            ));
+        }
+
+        let identifier = parse_identifier(&token)?;
+
+        // a name followed by brackets is an array element
+        if self.peek_is(0, &["("]) {
+            let open = self.next().unwrap();
+            self.nest(open.span)?;
+            let index = self.parse_sum()?;
+            let close = self.close_bracket(&open)?;
+            self.depth -= 1;
+
+            return Ok(Expression {
+                kind: ExpressionKind::Element {
+                    array: identifier.name,
+                    index: Box::new(index),
+                },
+                span: token.span.to(close),
+            });
+        }
+
+        Ok(Expression {
+            kind: ExpressionKind::Variable(identifier.name),
+            span: token.span,
+        })
+    }
+
+    // consumes the bracket closing open and returns its span
+    fn close_bracket(&mut self, open: &Token) -> Result<Span> {
+        match self.peek() {
+            Some(token) if token.text == ")" => Ok(self.next().unwrap().span),
+            _ => bail!(SyntaxError::new("Mismatched brackets", open.span)),
+        }
+    }
+}
+
+// real numbers too large to store become infinite, and integers too large to store
+// would otherwise quietly become real numbers
+fn check_range(number: Word, text: &str, span: Span) -> Result<Word> {
+    let is_integer = text.chars().all(|x| x.is_ascii_digit());
+
+    if number.as_float().is_infinite() || (is_integer && matches!(number, Word::Float(_))) {
+        bail!(SyntaxError::new(
+            format!("Number {text} is too large"),
+            span
+        ));
+    }
+
+    Ok(number)
+}
+
+fn binary(operator: Operator, left: Expression, right: Expression) -> Expression {
+    Expression {
+        span: left.span.to(right.span),
+        kind: ExpressionKind::Binary {
+            operator,
+            left: Box::new(left),
+            right: Box::new(right),
+        },
+    }
+}
+
+const COMPARISON_OPERATORS: [&str; 7] = ["==", "!=", "<>", "<", ">", "<=", ">="];
+
+// parses a condition, where span marks where a missing condition should be
+pub fn parse_condition(tokens: &[Token], span: Span) -> Result<Condition> {
+    parse_nested_condition(tokens, span, 0)
+}
+
+// parses a condition inside depth NOTs, brackets and ANDs or ORs, which count towards the
+// depth of the expressions it compares
+fn parse_nested_condition(tokens: &[Token], span: Span, depth: usize) -> Result<Condition> {
+    if tokens.is_empty() {
+        bail!(SyntaxError::new("Missing condition", span));
+    }
+
+    if depth > MAX_NESTING {
+        bail!(SyntaxError::new(
+            "Condition is nested too deeply",
+            tokens[0].span
+        ));
+    }
+
+    // OR binds loosest, then AND, then NOT
+    for keyword in ["OR", "AND"] {
+        let operands = split_top_level(tokens, keyword);
+
+        if operands.len() > 1 {
+            let operands = operands
+                .into_iter()
+                .map(|(operand, span)| parse_nested_condition(operand, span, depth + 1))
+                .collect::<Result<Vec<Condition>>>()?;
+
+            return Ok(match keyword {
+                "OR" => Condition::Or(operands),
+                _ => Condition::And(operands),
+            });
+        }
+    }
+
+    if is_keyword(&tokens[0], "NOT") {
+        let operand = parse_nested_condition(&tokens[1..], tokens[0].span.after(), depth + 1)?;
+
+        return Ok(Condition::Not(Box::new(operand)));
+    }
+
+    // brackets around a whole condition rather than an arithmetic expression
+    if tokens[0].text == "(" && closing_bracket(tokens) == Some(tokens.len() - 1) {
+        let inner = &tokens[1..tokens.len() - 1];
+
+        if is_condition(inner) {
+            return parse_nested_condition(inner, tokens[0].span.after(), depth + 1);
+        }
+    }
+
+    let comparison = match find_top_level(tokens, |x| COMPARISON_OPERATORS.contains(&x)) {
+        Some(comparison) => comparison,
+        None => bail!(SyntaxError::new(
+            "Missing comparison operator",
+            tokens[0].span.to(tokens[tokens.len() - 1].span)
+        )),
+    };
+
+    let operator = &tokens[comparison];
+
+    Ok(Condition::Compare {
+        comparison: match operator.text.as_str() {
+            "==" => Comparison::Equal,
+            "!=" | "<>" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            _ => Comparison::GreaterEqual,
+        },
+        left: parse_nested_expression(&tokens[..comparison], operator.span, depth)?,
+        right: parse_nested_expression(&tokens[comparison + 1..], operator.span.after(), depth)?,
+    })
+}
+
+// finds the first token outside of brackets that matches
+fn find_top_level(tokens: &[Token], matches: impl Fn(&str) -> bool) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text.as_str() {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            text if depth == 0 && matches(text) => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// splits tokens on a keyword outside of brackets, pairing each part with the span of a keyword
+// next to it in case the part is empty
+fn split_top_level<'a>(tokens: &'a [Token], keyword: &str) -> Vec<(&'a [Token], Span)> {
+    let mut parts = vec![];
+    let mut rest = tokens;
+    let mut last = None;
+
+    while let Some(i) = find_top_level(rest, |x| x.to_uppercase() == keyword) {
+        parts.push((&rest[..i], rest[i].span));
+        last = Some(rest[i].span);
+        rest = &rest[i + 1..];
+    }
+
+    if let Some(last) = last {
+        parts.push((rest, last.after()));
+    }
+
+    parts
+}
+
+// index of the bracket closing the one that opens tokens
+fn closing_bracket(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text.as_str() {
+            "(" => depth += 1,
+            ")" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// whether tokens form a condition rather than an arithmetic expression
+fn is_condition(tokens: &[Token]) -> bool {
+    tokens.first().is_some_and(|x| is_keyword(x, "NOT"))
+        || find_top_level(tokens, |x| {
+            COMPARISON_OPERATORS.contains(&x) || ["AND", "OR"].contains(&x.to_uppercase().as_str())
+        })
+        .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::lexer::tokenize;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source, 1).unwrap()
+    }
+
+    // an expression with every operation in brackets
+    fn show(expression: &Expression) -> String {
+        match &expression.kind {
+            ExpressionKind::Number(number) => number.to_string(),
+            ExpressionKind::String(literal) => format!("{literal:?}"),
+            ExpressionKind::Variable(name) => name.clone(),
+            ExpressionKind::Element { array, index } => format!("{array}[{}]", show(index)),
+            ExpressionKind::Negate(operand) => format!("-{}", show(operand)),
+            ExpressionKind::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let operator = match operator {
+                    Operator::Add => "+",
+                    Operator::Subtract => "-",
+                    Operator::Multiply => "*",
+                    Operator::Divide => "/",
+                    Operator::Modulus => "%",
+                    Operator::Exponentiate => "^",
+                };
+                format!("({} {} {})", show(left), operator, show(right))
+            }
+        }
+    }
+
+    fn show_condition(condition: &Condition) -> String {
+        match condition {
+            Condition::Compare {
+                comparison,
+                left,
+                right,
+            } => {
+                let comparison = match comparison {
+                    Comparison::Equal => "==",
+                    Comparison::NotEqual => "!=",
+                    Comparison::Less => "<",
+                    Comparison::Greater => ">",
+                    Comparison::LessEqual => "<=",
+                    Comparison::GreaterEqual => ">=",
+                };
+                format!("{} {} {}", show(left), comparison, show(right))
+            }
+            Condition::Not(operand) => format!("NOT [{}]", show_condition(operand)),
+            Condition::And(operands) | Condition::Or(operands) => {
+                let keyword = match condition {
+                    Condition::And(_) => " AND ",
+                    _ => " OR ",
+                };
+                let operands: Vec<String> = operands
+                    .iter()
+                    .map(|x| format!("[{}]", show_condition(x)))
+                    .collect();
+                operands.join(keyword)
+            }
+        }
+    }
+
+    fn expression(source: &str) -> Result<Expression> {
+        parse_expression(&tokens(source), Span::default())
+    }
+
+    fn condition(source: &str) -> Result<Condition> {
+        parse_condition(&tokens(source), Span::default())
+    }
+
+    // the message and span of the syntax error a parse failed with
+    fn error<T>(result: Result<T>) -> (String, Span) {
+        let Err(error) = result else {
+            panic!("expected a syntax error");
+        };
+        let error = error.downcast::<SyntaxError>().unwrap();
+
+        (error.message, error.span)
+    }
+
+    #[test]
+    fn expressions_nested_past_the_limit() {
+        let nested = |depth| {
+            let source = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+            parse_expression(&tokens(&source), Span::default())
+        };
+
+        assert!(nested(MAX_NESTING).is_ok());
+
+        // the first bracket past the limit
+        let (message, span) = error(nested(MAX_NESTING + 1));
+        assert_eq!(message, "Expression is nested too deeply");
+        assert_eq!(span.start, MAX_NESTING + 1);
+    }
+
+    #[test]
+    fn operator_chains_count_towards_the_limit() {
+        let chain = |length| {
+            let source = vec!["A"; length + 1].join(" + ");
+            parse_expression(&tokens(&source), Span::default())
+        };
+
+        assert!(chain(MAX_NESTING).is_ok());
+        assert_eq!(
+            error(chain(MAX_NESTING + 1)).0,
+            "Expression is nested too deeply"
+        );
+        assert_eq!(
+            error(parse_expression(
+                &tokens(&"-".repeat(MAX_NESTING + 1)),
+                Span::default()
+            ))
+            .0,
+            "Expression is nested too deeply"
+        );
+    }
+
+    #[test]
+    fn conditions_nested_past_the_limit() {
+        let source = format!("{}A == 1", "NOT ".repeat(MAX_NESTING + 1));
+
+        assert_eq!(
+            error(parse_condition(&tokens(&source), Span::default())).0,
+            "Condition is nested too deeply"
+        );
+    }
+
+    #[test]
+    fn inline_ifs_nested_past_the_limit() {
+        let source = format!("10 {}END", "IF A == 1 THEN ".repeat(MAX_NESTING + 1));
+
+        assert_eq!(
+            error(parse_line(&tokens(&source))).0,
+            "IF is nested too deeply"
+        );
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        let parsed = |source| show(&expression(source).unwrap());
+
+        assert_eq!(parsed("1 + 2 * 3 - 4"), "((1 + (2 * 3)) - 4)");
+        assert_eq!(parsed("8 / 4 / 2 % 3"), "(((8 / 4) / 2) % 3)");
+        assert_eq!(parsed("(1 + 2) * 3"), "((1 + 2) * 3)");
+        assert_eq!(parsed("2 ^ 3 ^ 2"), "(2 ^ (3 ^ 2))");
+        assert_eq!(parsed("2 * A(I + 1) ^ 2"), "(2 * (A[(I + 1)] ^ 2))");
+    }
+
+    #[test]
+    fn signs() {
+        let parsed = |source| show(&expression(source).unwrap());
+
+        // a minus before a number is part of it, except before ^
+        assert!(matches!(
+            expression("-5").unwrap().kind,
+            ExpressionKind::Number(Word::Int(-5))
+        ));
+        assert_eq!(parsed("-2 ^ 2"), "-(2 ^ 2)");
+        assert_eq!(parsed("2 ^ -1"), "(2 ^ -1)");
+        assert_eq!(parsed("-X * 3"), "(-X * 3)");
+        assert_eq!(parsed("- -X"), "--X");
+        assert_eq!(parsed("+X - +2"), "(X - 2)");
+        assert_eq!(parsed("1.5 * -0.5"), "(1.5 * -0.5)");
+    }
+
+    #[test]
+    fn expression_spans() {
+        let parsed = expression("A + (B * 2)").unwrap();
+        assert_eq!((parsed.span.start, parsed.span.end), (1, 12));
+
+        let ExpressionKind::Binary { right, .. } = parsed.kind else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!((right.span.start, right.span.end), (5, 12));
+    }
+
+    #[test]
+    fn expression_errors() {
+        let at = |start, end| Span {
+            line: 1,
+            start,
+            end,
+        };
+
+        assert_eq!(
+            error(parse_expression(&[], at(7, 8))),
+            ("Missing expression".to_string(), at(7, 8))
+        );
+        assert_eq!(
+            error(expression("2 3")),
+            ("Missing operator before 3".to_string(), at(3, 4))
+        );
+        assert_eq!(
+            error(expression("1 +")),
+            ("Incomplete expression".to_string(), at(4, 5))
+        );
+        assert_eq!(
+            error(expression("* 2")),
+            ("Missing operand before *".to_string(), at(1, 2))
+        );
+        assert_eq!(
+            error(expression("(1 + 2")),
+            ("Mismatched brackets".to_string(), at(1, 2))
+        );
+        assert_eq!(
+            error(expression("1 + 2)")),
+            ("Mismatched brackets".to_string(), at(6, 7))
+        );
+        assert_eq!(
+            error(expression("1 = 2")),
+            ("Unexpected token =".to_string(), at(3, 4))
+        );
+        assert_eq!(
+            error(expression("X + PRINT")),
+            ("PRINT is a reserved keyword".to_string(), at(5, 10))
+        );
+        assert_eq!(
+            error(expression("99999999999")),
+            ("Number 99999999999 is too large".to_string(), at(1, 12))
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let parsed = |source| show_condition(&condition(source).unwrap());
+
+        assert_eq!(parsed("A + 1 <= B * 2"), "(A + 1) <= (B * 2)");
+        assert_eq!(parsed("A <> B"), "A != B");
+
+        // OR binds loosest, then AND, then NOT
+        assert_eq!(
+            parsed("A == 1 OR B == 2 AND NOT C == 3"),
+            "[A == 1] OR [[B == 2] AND [NOT [C == 3]]]"
+        );
+        assert_eq!(
+            parsed("(A == 1 OR B == 2) AND C == 3"),
+            "[[A == 1] OR [B == 2]] AND [C == 3]"
+        );
+
+        // brackets around an expression are not a condition
+        assert_eq!(parsed("(A + 1) * 2 > 3"), "((A + 1) * 2) > 3");
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert_eq!(error(condition("A + 1")).0, "Missing comparison operator");
+        assert_eq!(error(condition("A == 1 AND")).0, "Missing condition");
+        assert_eq!(error(condition("A ==")).0, "Missing expression");
+        assert_eq!(
+            error(parse_condition(&[], Span::default())).0,
+            "Missing condition"
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let line = parse_line(&tokens("20 PRINT X")).unwrap();
+        assert_eq!(line.number.number, 20);
+        assert_eq!((line.span.start, line.span.end), (4, 9));
+        assert!(matches!(line.statement, Statement::Print(_)));
+
+        // commands are not case sensitive, and THEN takes any command
+        let line = parse_line(&tokens("30 if X > 1 then print X")).unwrap();
+        assert!(matches!(
+            line.statement,
+            Statement::IfThen { ref statement, .. } if matches!(**statement, Statement::Print(_))
+        ));
+    }
+
+    #[test]
+    fn line_errors() {
+        assert_eq!(error(parse_line(&tokens("20"))).0, "Incomplete statement");
+        assert_eq!(
+            error(parse_line(&tokens("X PRINT 1"))).0,
+            "Invalid line number"
+        );
+        assert_eq!(
+            error(parse_line(&tokens("20 SHOUT 1"))).0,
+            "Invalid symbol SHOUT"
+        );
+        assert_eq!(
+            error(parse_line(&tokens("20 GOTO X"))).0,
+            "Cannot GOTO a variable"
+        );
+        assert_eq!(
+            error(parse_line(&tokens("20 GOSUB 1.5"))).0,
+            "Invalid line number"
+        );
+        assert_eq!(
+            error(parse_line(&tokens("20 IF X > 1 THEN WHILE X > 1"))).0,
+            "WHILE cannot follow THEN on the same line"
+        );
+    }
+}
@@ -13,7 +13,7 @@ impl SymbolTable {
         self.data.push(table_entry);
     }
 
-    pub fn find(&self, symbol: i32, entry_type: TableEntryType) -> Option<TableEntry> {
+    pub fn find(&self, symbol: &str, entry_type: TableEntryType) -> Option<TableEntry> {
         self.data
             .iter()
             .find(|table_entry| {
@@ -21,4 +21,19 @@ impl SymbolTable {
             })
             .cloned()
     }
+
+    pub fn entries(&self, entry_type: TableEntryType) -> impl Iterator<Item = &TableEntry> {
+        self.data
+            .iter()
+            .filter(move |table_entry| table_entry.entry_type == entry_type)
+    }
+
+    pub fn entries_mut(
+        &mut self,
+        entry_type: TableEntryType,
+    ) -> impl Iterator<Item = &mut TableEntry> {
+        self.data
+            .iter_mut()
+            .filter(move |table_entry| table_entry.entry_type == entry_type)
+    }
 }
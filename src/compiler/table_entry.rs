@@ -3,11 +3,14 @@ pub enum TableEntryType {
     Constant,
     LineNumber,
     Variable,
+    Array,
+    StringConstant,
+    StringVariable,
 }
 
 #[derive(Clone)]
 pub struct TableEntry {
-    pub symbol: i32,
+    pub symbol: String,
     pub entry_type: TableEntryType,
     pub location: u32,
 }
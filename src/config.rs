@@ -1,5 +1,14 @@
+//! Sizes shared by the compiler and the simulator.
+
+/// Number of words in Simpletron memory.
 pub const MEMORY: u32 = 10000;
+/// Radix of words in SML files and of instructions typed at the simulator.
 pub const INSTRUCTIONS_RADIX: u32 = 16;
-// number separating operation from operand
+/// Number separating operation from operand, so an instruction is
+/// `operation_code * INSTRUCTIONS_SEP + operand`.
 pub const INSTRUCTIONS_SEP: u32 =
     INSTRUCTIONS_RADIX * INSTRUCTIONS_RADIX * INSTRUCTIONS_RADIX * INSTRUCTIONS_RADIX;
+/// Maximum depth of nested subroutine calls.
+pub const CALL_STACK_SIZE: usize = 1000;
+/// Characters reserved for a string variable that is not given a size with DIM.
+pub const STRING_CAPACITY: u32 = 80;
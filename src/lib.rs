@@ -0,0 +1,38 @@
+//! Compiler and simulator for Simple, a small BASIC-like language, and the Simpletron
+//! Machine Language (SML) it compiles to.
+//!
+//! A program goes through three stages:
+//!
+//! - [`Compiler::compile`] turns Simple source into a [`Program`], or returns every
+//!   [`Diagnostic`] it found.
+//! - [`Program::to_sml`] and [`Program::from_sml`] write and read the `.sml` file format.
+//! - [`Simulator::simulate`] runs a loaded program and reports an [`Outcome`].
+//!
+//! ```
+//! use simple::{BufferIo, Compiler, Outcome, Simulator};
+//!
+//! let program = Compiler::new()
+//!     .compile("10 INPUT X\n20 PRINT X * 2\n30 END\n")
+//!     .unwrap();
+//!
+//! let mut io = BufferIo::new("21\n");
+//! let mut simulator = Simulator::with_io(&mut io);
+//! simulator.load(&program).unwrap();
+//!
+//! assert_eq!(simulator.simulate(), Outcome::Halted);
+//! drop(simulator);
+//! assert_eq!(io.output(), "42\n");
+//! ```
+
+#![warn(missing_docs)]
+
+pub mod compiler;
+pub mod config;
+pub mod program;
+pub mod simulator;
+pub mod word;
+
+pub use compiler::{Compiler, Diagnostic, OptimizationLevel, Severity, Span, Suggestion};
+pub use program::Program;
+pub use simulator::{BufferIo, Io, Outcome, Simulator, StdIo};
+pub use word::Word;
@@ -1,11 +1,10 @@
-pub mod compiler;
-pub mod config;
-pub mod simulator;
-
-use clap::{Parser, Subcommand};
-use compiler::Compiler;
-use simulator::Simulator;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
+use simple::{Compiler, Diagnostic, OptimizationLevel, Outcome, Program, Severity, Simulator};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 /// Simple compiler and simulator
 #[derive(Parser, Debug)]
@@ -23,32 +22,229 @@ enum Commands {
 
         #[clap(short, long)]
         out: Option<PathBuf>,
+
+        /// How to print errors and warnings
+        #[clap(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+
+        /// Optimization level: 0 for none, 1 for folding and removing redundant loads and
+        /// stores, 2 to also cache values across statements and thread jumps
+        #[clap(short = 'O', default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+        opt_level: u8,
+
+        /// Print how many instructions and data words the optimizer saved, as a JSON object
+        /// with "type":"report" among the diagnostics with --message-format=json
+        #[clap(long)]
+        report: bool,
     },
     /// Simulate SML with the Simpletron
-    Sim { path: PathBuf },
+    Sim {
+        path: PathBuf,
+
+        /// Stop after this many instructions
+        #[clap(long)]
+        max_steps: Option<u64>,
+    },
 }
 
-// TODO: implement floating-point numbers
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum MessageFormat {
+    /// Source snippets for people
+    #[default]
+    Human,
+    /// One JSON object per line for tools
+    Json,
+}
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
 
     match &args.cmd {
-        Commands::Com { path, out } => {
-            let mut compiler = Compiler::new();
+        Commands::Com {
+            path,
+            out,
+            message_format,
+            opt_level,
+            report,
+        } => {
+            let optimization_level = match opt_level {
+                0 => OptimizationLevel::None,
+                1 => OptimizationLevel::Basic,
+                _ => OptimizationLevel::Full,
+            };
+
+            compile(
+                path,
+                out.as_deref().unwrap_or(Path::new("./out.sml")),
+                *message_format,
+                optimization_level,
+                *report,
+            )
+        }
+        Commands::Sim { path, max_steps } => simulate(path, *max_steps),
+    }
+}
+
+// loads an SML file and runs it on the terminal
+fn simulate(path: &Path, max_steps: Option<u64>) -> ExitCode {
+    println!("*** Welcome to Simpletron! ***");
+    println!();
+
+    let Ok(text) = fs::read_to_string(path) else {
+        println!("*** Failed to open file {} ***", path.to_string_lossy());
+        return ExitCode::FAILURE;
+    };
+
+    let mut simpletron = Simulator::new();
+    simpletron.set_step_limit(max_steps);
+
+    if let Err(error) = Program::from_sml(&text).and_then(|x| simpletron.load(&x)) {
+        println!("*** {} ***", error);
+        return ExitCode::FAILURE;
+    }
+
+    println!("*** Program loading completed ***");
+    println!();
+    println!("*** Program execution begins ***");
+    println!();
+
+    match simpletron.simulate() {
+        Outcome::Halted => {
+            println!();
+            println!("*** Simpletron execution terminated ***");
+            ExitCode::SUCCESS
+        }
+        Outcome::Crashed { reason, .. } => {
+            println!();
+            println!("*** {} ***", reason);
+            println!("*** Simpletron execution abnormally terminated ***");
+            ExitCode::FAILURE
+        }
+        Outcome::StepLimit => {
+            println!();
+            println!("*** Step limit of {} reached ***", max_steps.unwrap_or(0));
+            println!("*** Simpletron execution abnormally terminated ***");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-            compiler.compile(
-                path.to_path_buf(),
-                out.to_owned().unwrap_or(PathBuf::from("./out.sml")),
+// compiles a file and writes the program, printing diagnostics along the way
+fn compile(
+    path: &Path,
+    out: &Path,
+    message_format: MessageFormat,
+    optimization_level: OptimizationLevel,
+    print_report: bool,
+) -> ExitCode {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(_) => {
+            eprintln!(
+                "*** Failed to open file {} for reading ***",
+                path.to_string_lossy()
             );
+            return ExitCode::FAILURE;
         }
-        Commands::Sim { path } => {
-            let mut simpletron = Simulator::new();
+    };
+
+    let mut compiler = Compiler::new();
+    compiler.set_optimization_level(optimization_level);
+    let result = compiler.compile(&source);
+
+    let diagnostics = match &result {
+        Ok(_) => compiler.warnings(),
+        Err(diagnostics) => diagnostics,
+    };
+    report(diagnostics, path, &source, message_format);
+
+    let Ok(program) = result else {
+        return ExitCode::FAILURE;
+    };
+
+    if fs::write(out, program.to_sml()).is_err() {
+        eprintln!("*** Failed to write to file {} ***", out.to_string_lossy());
+        return ExitCode::FAILURE;
+    }
 
-            if simpletron.load(path.to_path_buf()).is_err() {
-                return;
+    if print_report {
+        // the same program compiled as written
+        let mut unoptimized = Compiler::new();
+        unoptimized.set_optimization_level(OptimizationLevel::None);
+        let before = match unoptimized.compile(&source) {
+            Ok(_) => unoptimized.instruction_count(),
+            Err(_) => compiler.instruction_count(),
+        };
+        let after = compiler.instruction_count();
+        let saved = compiler.data_words_saved();
+
+        match message_format {
+            MessageFormat::Human => {
+                println!("*** Instructions: {before} before optimizing, {after} after ***");
+                println!(
+                    "*** Reusing temporaries saved {} data {} ***",
+                    saved,
+                    plural(saved as usize, "word")
+                );
             }
-            simpletron.simulate();
+            MessageFormat::Json => println!(
+                "{{\"type\":\"report\",\"instructions_before\":{before},\"instructions_after\":{after},\"data_words_saved\":{saved}}}"
+            ),
         }
     }
+
+    if message_format == MessageFormat::Human {
+        println!("*** Compilation finished successfully! ***");
+    }
+
+    ExitCode::SUCCESS
+}
+
+// prints diagnostics to stderr, or as JSON lines to stdout for tools
+fn report(diagnostics: &[Diagnostic], path: &Path, source: &str, message_format: MessageFormat) {
+    let path = path.to_string_lossy();
+
+    if message_format == MessageFormat::Json {
+        for diagnostic in diagnostics {
+            println!("{}", diagnostic.to_json(&path));
+        }
+        return;
+    }
+
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(&path, source));
+    }
+
+    let count = |severity| {
+        diagnostics
+            .iter()
+            .filter(|x| x.severity == severity)
+            .count()
+    };
+    let errors = count(Severity::Error);
+    let warnings = count(Severity::Warning);
+
+    if errors > 0 {
+        eprintln!(
+            "error: could not compile {} due to {} previous {}",
+            path,
+            errors,
+            plural(errors, "error")
+        );
+    } else if warnings > 0 {
+        eprintln!(
+            "warning: {} generated {} {}",
+            path,
+            warnings,
+            plural(warnings, "warning")
+        );
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_string()
+    } else {
+        format!("{word}s")
+    }
 }
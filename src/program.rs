@@ -0,0 +1,36 @@
+//! The SML program format shared by the compiler and the simulator.
+
+use crate::word::Word;
+use anyhow::{bail, Result};
+
+/// A compiled SML program, holding the word for each memory address from 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    /// Instructions from address 0, followed by the data area at the end of memory.
+    pub words: Vec<Word>,
+}
+
+impl Program {
+    /// Parses an SML file, one word per line.
+    pub fn from_sml(text: &str) -> Result<Program> {
+        let mut words = vec![];
+
+        for (i, line) in text.lines().enumerate() {
+            match Word::from_sml(line.trim()) {
+                Ok(word) => words.push(word),
+                Err(error) => bail!("{} on line {}", error, i + 1),
+            }
+        }
+
+        Ok(Program { words })
+    }
+
+    /// Formats the program as an SML file, one word per line.
+    pub fn to_sml(&self) -> String {
+        self.words
+            .iter()
+            .map(|x| x.to_sml())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
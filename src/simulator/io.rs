@@ -0,0 +1,90 @@
+use anyhow::{bail, Result};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+/// Where a running program reads its input and writes its output.
+pub trait Io {
+    /// Reads a line without its line ending, or `None` once the input has run out.
+    fn read_line(&mut self) -> Result<Option<String>>;
+
+    /// Writes text, which holds a whole line ending in `\n` for WRITE, and the characters
+    /// of a string, which need not end a line, for WRITE_STR.
+    fn write(&mut self, text: &str) -> Result<()>;
+}
+
+// lending an Io to a simulator lets the caller inspect it after the run
+impl<T: Io + ?Sized> Io for &mut T {
+    fn read_line(&mut self) -> Result<Option<String>> {
+        (**self).read_line()
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        (**self).write(text)
+    }
+}
+
+/// The terminal the simulator was started from.
+#[derive(Default)]
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(line.trim_end_matches(['\n', '\r']).to_string())),
+            Err(_) => bail!("Failed to read line"),
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        let mut stdout = io::stdout();
+
+        if stdout
+            .write_all(text.as_bytes())
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            bail!("Failed to write output");
+        }
+
+        Ok(())
+    }
+}
+
+/// Input given up front and output collected in memory, for tests and other services.
+#[derive(Default)]
+pub struct BufferIo {
+    input: VecDeque<String>,
+    output: String,
+}
+
+impl BufferIo {
+    /// Reads the lines of `input`, then reports the end of input.
+    pub fn new(input: &str) -> BufferIo {
+        BufferIo {
+            input: input.lines().map(|x| x.to_string()).collect(),
+            output: String::new(),
+        }
+    }
+
+    /// Everything written so far.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+}
+
+impl Io for BufferIo {
+    fn read_line(&mut self) -> Result<Option<String>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.output.push_str(text);
+
+        Ok(())
+    }
+}
@@ -1,237 +1,391 @@
+//! The Simpletron, which runs SML programs.
+
+mod io;
 mod operations;
 
-use crate::config::{INSTRUCTIONS_RADIX, INSTRUCTIONS_SEP, MEMORY};
+pub use io::{BufferIo, Io, StdIo};
+
+use crate::{
+    config::{CALL_STACK_SIZE, INSTRUCTIONS_RADIX, INSTRUCTIONS_SEP, MEMORY},
+    program::Program,
+    word::Word,
+};
 use anyhow::{bail, Result};
 use operations::OPERATION_TABLE;
-use std::{
-    fs::File,
-    io::{self, prelude::*, BufReader},
-    path::PathBuf,
-};
+use std::fmt::Write;
 
 #[derive(PartialEq, Eq)]
-pub enum State {
+enum State {
     Running,
     Halted,
     Crashed,
 }
 
-pub struct Simulator {
+/// How a call to [`Simulator::simulate`] ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The program ran a HALT instruction.
+    Halted,
+    /// An instruction failed, such as a division by zero or an invalid operation.
+    Crashed {
+        /// What went wrong, such as `Attempt to divide by zero`.
+        reason: String,
+        /// Address of the instruction that failed.
+        address: u32,
+    },
+    /// The program was still running after the step limit.
+    StepLimit,
+}
+
+/// A Simpletron with its registers and memory, reading and writing through an [`Io`].
+pub struct Simulator<'a> {
     state: State,
-    pub(super) accumulator: i32,
+    pub(super) accumulator: Word,
+    pub(super) index_register: i32,
     instruction_counter: u32,
     instruction_register: i32,
     operation_code: u32,
     pub(super) operand: u32,
-    pub(super) memory: Vec<i32>,
+    pub(super) memory: Vec<Word>,
+    call_stack: Vec<u32>,
     debug: bool,
+    step_limit: Option<u64>,
+    io: Box<dyn Io + 'a>,
 }
 
-impl Simulator {
-    pub fn new() -> Simulator {
-        println!("*** Welcome to Simpletron! ***");
-        println!();
+impl Simulator<'static> {
+    /// A simulator that reads from stdin and writes to stdout.
+    pub fn new() -> Simulator<'static> {
+        Simulator::with_io(StdIo)
+    }
+}
 
+impl<'a> Simulator<'a> {
+    /// A simulator that reads and writes through `io`, which can be borrowed with
+    /// `&mut` to inspect it after the run.
+    pub fn with_io(io: impl Io + 'a) -> Simulator<'a> {
         Simulator {
             state: State::Halted,
-            accumulator: 0,
+            accumulator: Word::default(),
+            index_register: 0,
             instruction_counter: 0,
             instruction_register: 0,
             operation_code: 0,
             operand: 0,
-            memory: vec![0; MEMORY as usize],
+            memory: vec![Word::default(); MEMORY as usize],
+            call_stack: vec![],
             debug: false,
+            step_limit: None,
+            io: Box::new(io),
         }
     }
 
-    pub fn set_state(&mut self, state: State) {
+    /// Stops [`Simulator::simulate`] after this many instructions, for programs that may
+    /// never halt. There is no limit by default.
+    pub fn set_step_limit(&mut self, step_limit: Option<u64>) {
+        self.step_limit = step_limit;
+    }
+
+    /// The accumulator register.
+    pub fn accumulator(&self) -> Word {
+        self.accumulator
+    }
+
+    /// Every word of memory.
+    pub fn memory(&self) -> &[Word] {
+        &self.memory
+    }
+
+    fn set_state(&mut self, state: State) {
         self.state = state;
     }
 
-    pub fn set_instruction_counter(&mut self, instruction_counter: u32) {
+    fn set_instruction_counter(&mut self, instruction_counter: u32) {
         self.instruction_counter = instruction_counter;
     }
 
+    /// Whether to write a [`Simulator::dump`] after every instruction, which the
+    /// SML_DEBUG instruction also sets.
     pub fn set_debug(&mut self, debug: bool) {
         self.debug = debug;
     }
 
-    // load program from file
-    pub fn load(&mut self, path: PathBuf) -> Result<()> {
-        let file = match File::open(&path) {
-            Ok(file) => file,
-            Err(_) => {
-                println!("*** Failed to open file {} ***", &path.to_string_lossy());
-                bail!("Failed to open file");
-            }
-        };
+    // address of the array element selected by the index register,
+    // where the operand points to a word holding the array length
+    fn indexed_address(&self) -> Result<usize> {
+        let length = self.memory[self.operand as usize].as_int()?;
+
+        if self.index_register < 0 || self.index_register >= length {
+            bail!("Array index {} out of bounds", self.index_register);
+        }
 
-        let reader = BufReader::new(file);
+        let address = self.operand as usize + 1 + self.index_register as usize;
 
-        for (i, line) in reader.lines().enumerate() {
-            self.memory[i] = i32::from_str_radix(line.unwrap().trim(), INSTRUCTIONS_RADIX)
-                .unwrap_or_else(|_| panic!("Invalid token on line {}", i + 1))
+        if address >= self.memory.len() {
+            bail!("Invalid memory address {:x}", address);
         }
 
-        println!("*** Program loading completed ***");
-        println!();
+        Ok(address)
+    }
+
+    // number of characters the string buffer whose length is at ptr can hold
+    fn string_capacity(&self, ptr: usize) -> Result<usize> {
+        if ptr == 0 {
+            bail!("Invalid memory address {:x}", ptr);
+        }
+
+        let capacity = self.memory[ptr - 1].as_int()?.max(0) as usize;
+
+        if ptr + capacity >= self.memory.len() {
+            bail!("Invalid memory address {:x}", ptr + capacity);
+        }
+
+        Ok(capacity)
+    }
+
+    // stores a string as its length followed by its characters
+    fn store_string(&mut self, ptr: usize, chars: &[char]) -> Result<()> {
+        if ptr + chars.len() >= self.memory.len() {
+            bail!("Invalid memory address {:x}", ptr + chars.len());
+        }
+
+        self.memory[ptr] = Word::Int(chars.len() as i32);
+
+        // assign each character to memory
+        for (i, char) in chars.iter().enumerate() {
+            self.memory[ptr + i + 1] = Word::Int(*char as i32);
+        }
 
         Ok(())
     }
 
-    // load program from command-line input
+    fn push_return_address(&mut self) -> Result<()> {
+        if self.call_stack.len() >= CALL_STACK_SIZE {
+            bail!("Call stack overflow");
+        }
+
+        self.call_stack.push(self.instruction_counter);
+
+        Ok(())
+    }
+
+    fn pop_return_address(&mut self) -> Result<()> {
+        match self.call_stack.pop() {
+            Some(instruction_counter) => {
+                self.instruction_counter = instruction_counter;
+                Ok(())
+            }
+            None => bail!("Return without matching call"),
+        }
+    }
+
+    /// Copies a program into memory, starting at address 0, and clears the rest.
+    pub fn load(&mut self, program: &Program) -> Result<()> {
+        if program.words.len() > self.memory.len() {
+            bail!(
+                "Program of {} words does not fit in memory",
+                program.words.len()
+            );
+        }
+
+        self.memory.fill(Word::default());
+        self.memory[..program.words.len()].copy_from_slice(&program.words);
+
+        Ok(())
+    }
+
+    /// Loads a program typed one word at a time, prompting with each address.
     pub fn input(&mut self) -> Result<()> {
-        println!("*** Please enter your program one instruction ***");
-        println!("*** (or data word) at a time. I will type the ***");
-        println!("*** location number and a question mark (?). ***");
-        println!("*** You then type the word for that location. ***");
-        println!("*** Type the sentinel -10000 to stop entering ***");
-        println!("*** your program. ***");
-        println!();
+        self.io.write(
+            "*** Please enter your program one instruction ***\n\
+             *** (or data word) at a time. I will type the ***\n\
+             *** location number and a question mark (?). ***\n\
+             *** You then type the word for that location. ***\n\
+             *** Type the sentinel -10000 to stop entering ***\n\
+             *** your program. ***\n\n",
+        )?;
 
         for i in 0..self.memory.len() {
-            print!("{i:0>2} ? ");
-            io::stdout().flush().unwrap();
+            self.io.write(&format!("{i:0>2} ? "))?;
 
-            let data = match self.read_instruction() {
-                Ok(data) => data,
-                Err(_) => {
-                    println!("*** Invalid token ***");
-                    bail!("Invalid token");
-                }
-            };
+            let data = self.read_instruction()?;
 
             if data == -10000 {
                 break;
             }
 
-            self.memory[i] = data;
+            self.memory[i] = Word::Int(data);
         }
 
-        println!();
-        println!("*** Program loading completed ***");
-        println!();
-
         Ok(())
     }
 
-    pub fn simulate(&mut self) {
-        println!("*** Program execution begins ***");
-        println!();
-
+    /// Runs the program from address 0 until it halts, crashes or reaches the step limit.
+    pub fn simulate(&mut self) -> Outcome {
         self.state = State::Running;
+        self.instruction_counter = 0;
+        self.call_stack.clear();
+
+        let mut steps = 0;
 
         while self.state == State::Running {
-            match self.step() {
-                Ok(()) => {}
-                Err(error) => {
-                    println!();
-                    println!("*** {} ***", error);
-                    println!("*** Simpletron execution abnormally terminated ***");
-                }
+            if self.step_limit.is_some_and(|x| steps >= x) {
+                return Outcome::StepLimit;
+            }
+            steps += 1;
+
+            let address = self.instruction_counter;
+
+            if let Err(error) = self.step() {
+                self.state = State::Crashed;
+
+                return Outcome::Crashed {
+                    reason: error.to_string(),
+                    address,
+                };
             }
         }
+
+        Outcome::Halted
     }
 
     // executes current instruction
     fn step(&mut self) -> Result<()> {
-        self.instruction_register = self.memory[self.instruction_counter as usize];
+        let address = self.instruction_counter as usize;
+
+        if address >= self.memory.len() {
+            bail!("Invalid memory address {:x}", address);
+        }
+
+        self.instruction_register = match self.memory[address] {
+            Word::Int(instruction) if instruction >= 0 => instruction,
+            _ => bail!("Invalid instruction at {:x}", address),
+        };
 
         self.operation_code = (self.instruction_register / INSTRUCTIONS_SEP as i32) as u32;
         self.operand = (self.instruction_register % INSTRUCTIONS_SEP as i32) as u32;
 
+        if self.operand as usize >= self.memory.len() {
+            bail!("Invalid memory address {:x}", self.operand);
+        }
+
         // find operation in operation table
         let operation = OPERATION_TABLE.get(&self.operation_code).copied();
 
         // check if operation exists
         match operation {
             // call operation on self
-            Some(operation) => match operation(self) {
-                Ok(()) => {}
-                // error handling
-                Err(error) => {
-                    self.state = State::Crashed;
-                    return Err(error);
-                }
-            },
-            None => {
-                println!("Invalid operation {:x}", self.operation_code);
-                self.state = State::Halted;
-            }
+            Some(operation) => operation(self)?,
+            None => bail!("Invalid operation {:x}", self.operation_code),
         }
 
         // move to next instruction
-        self.instruction_counter += 1;
+        self.instruction_counter = self.instruction_counter.wrapping_add(1);
 
         if self.debug {
-            self.dump();
+            let dump = self.dump();
+            self.io.write(&dump)?;
         }
 
         Ok(())
     }
 
-    pub fn dump(&self) {
-        println!("REGISTERS:");
-        println!(
-            "accumulator\t\t{}{:0>4x}",
-            self.sign(self.accumulator),
-            self.accumulator
-        );
-        println!("instruction_counter\t   {:0>2x}", self.instruction_counter);
-        println!(
+    /// Formats the registers and the first hundred words of memory.
+    pub fn dump(&self) -> String {
+        let mut output = String::new();
+
+        writeln!(output, "REGISTERS:").unwrap();
+        writeln!(
+            output,
+            "accumulator\t\t{}",
+            self.format_word(self.accumulator)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "index_register\t\t{}{:0>4x}",
+            self.sign(self.index_register),
+            self.index_register
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "instruction_counter\t   {:0>2x}",
+            self.instruction_counter
+        )
+        .unwrap();
+        writeln!(
+            output,
             "instruction_register\t{}{:0>4x}",
             self.sign(self.instruction_register),
             self.instruction_register
-        );
-        println!("operation_code\t\t   {:0>2x}", self.instruction_counter);
-        println!("operand\t\t\t   {:0>2x}", self.instruction_counter);
-
-        println!();
-        println!("MEMORY");
-        print!("  ");
+        )
+        .unwrap();
+        writeln!(output, "operation_code\t\t   {:0>2x}", self.operation_code).unwrap();
+        writeln!(output, "operand\t\t\t   {:0>2x}", self.operand).unwrap();
+
+        writeln!(output).unwrap();
+        writeln!(output, "MEMORY").unwrap();
+        write!(output, "  ").unwrap();
         for i in 0..10 {
-            print!("     {i}");
+            write!(output, "     {i}").unwrap();
         }
-        println!();
+        writeln!(output).unwrap();
         for i in 0..10 {
-            print!("{i}0");
+            write!(output, "{i}0").unwrap();
 
             for j in 0..10 {
-                let data = self.memory[i * 10 + j];
-                print!(" {}{:0>4x}", self.sign(data), data)
+                write!(output, " {}", self.format_word(self.memory[i * 10 + j])).unwrap();
             }
-            println!();
+            writeln!(output).unwrap();
         }
+
+        output
     }
 
-    pub fn read_instruction(&self) -> Result<i32> {
-        let mut data = String::new();
-        if io::stdin().read_line(&mut data).is_err() {
-            bail!("Failed to read line")
+    // the next line of input, which running out of counts as an error
+    fn read_line(&mut self) -> Result<String> {
+        match self.io.read_line()? {
+            Some(line) => Ok(line),
+            None => bail!("Unexpected end of input"),
         }
+    }
+
+    fn read_instruction(&mut self) -> Result<i32> {
+        let data = self.read_line()?;
 
-        Ok(i32::from_str_radix(data.trim(), INSTRUCTIONS_RADIX).expect("Invalid input"))
+        match i32::from_str_radix(data.trim(), INSTRUCTIONS_RADIX) {
+            Ok(data) => Ok(data),
+            Err(_) => bail!("Invalid token"),
+        }
     }
 
-    pub fn read_decimal(&self) -> Result<i32> {
-        let mut data = String::new();
-        if io::stdin().read_line(&mut data).is_err() {
-            bail!("Failed to read line")
+    fn read_decimal(&mut self) -> Result<Word> {
+        let data = self.read_line()?;
+
+        match Word::parse_decimal(data.trim()) {
+            Ok(data) => Ok(data),
+            Err(_) => bail!("Invalid token"),
         }
+    }
 
-        Ok(data.trim().parse::<i32>().expect("Invalid input"))
+    fn read_string(&mut self) -> Result<String> {
+        self.read_line()
     }
 
-    pub fn read_string(&self) -> Result<String> {
-        let mut data = String::new();
-        if io::stdin().read_line(&mut data).is_err() {
-            bail!("Failed to read line")
-        }
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.io.write(text)
+    }
 
-        Ok(data)
+    // formats a word for memory dumps
+    fn format_word(&self, word: Word) -> String {
+        match word {
+            Word::Int(x) => format!("{}{:0>4x}", self.sign(x), x.unsigned_abs()),
+            Word::Float(x) => format!("{x:+.2}"),
+        }
     }
 
-    pub fn sign(&self, x: i32) -> char {
+    fn sign(&self, x: i32) -> char {
         if x < 0 {
             '-'
         } else {
@@ -240,7 +394,7 @@ impl Simulator {
     }
 }
 
-impl Default for Simulator {
+impl Default for Simulator<'static> {
     fn default() -> Self {
         Self::new()
     }
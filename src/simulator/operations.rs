@@ -1,4 +1,5 @@
 use super::{Simulator, State};
+use crate::word::Word;
 use anyhow::{bail, Result};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
@@ -6,10 +7,7 @@ use std::collections::HashMap;
 type Operation = fn(&mut Simulator) -> Result<()>;
 
 const READ: Operation = |simulator| {
-    let data = match simulator.read_decimal() {
-        Ok(data) => data,
-        Err(_) => bail!("Invalid token"),
-    };
+    let data = simulator.read_decimal()?;
 
     simulator.memory[simulator.operand as usize] = data;
 
@@ -17,39 +15,49 @@ const READ: Operation = |simulator| {
 };
 
 const WRITE: Operation = |simulator| {
-    // TODO: output to disk
-    println!("{}", simulator.memory[simulator.operand as usize]);
+    let word = simulator.memory[simulator.operand as usize];
+    simulator.write(&format!("{word}\n"))?;
 
     Ok(())
 };
 
 const READ_STR: Operation = |simulator| {
-    let data = match simulator.read_string() {
-        Ok(data) => data,
-        Err(_) => bail!("Invalid token"),
-    };
+    let data = simulator.read_string()?;
+    let chars: Vec<char> = data.chars().collect();
 
     // first address = length of string
     let ptr = simulator.operand as usize;
-    simulator.memory[ptr] = data.len() as i32;
+    simulator.store_string(ptr, &chars)
+};
 
-    // assign each character to memory
-    for (i, char) in data.chars().enumerate() {
-        simulator.memory[ptr + i + 1] = char as i32;
-    }
+// like READ_STR, but keeps only as many characters as fit the buffer, whose capacity is
+// stored before its length
+const READ_STR_BUFFER: Operation = |simulator| {
+    let data = simulator.read_string()?;
 
-    Ok(())
+    let ptr = simulator.operand as usize;
+    let capacity = simulator.string_capacity(ptr)?;
+
+    let chars: Vec<char> = data.chars().take(capacity).collect();
+    simulator.store_string(ptr, &chars)
 };
 
 const WRITE_STR: Operation = |simulator| {
     let ptr = simulator.operand as usize;
-    let length = simulator.memory[ptr] as usize;
+    let length = simulator.memory[ptr].as_int()?.max(0) as usize;
+
+    if ptr + length >= simulator.memory.len() {
+        bail!("Invalid memory address {:x}", ptr + length);
+    }
 
-    // write each character individually
+    let mut text = String::new();
     for i in 1..=length {
-        print!("{}", simulator.memory[ptr + i] as u8 as char);
+        let char = char::from_u32(simulator.memory[ptr + i].as_int()? as u32).unwrap_or('?');
+        text.push(char);
     }
 
+    simulator.write(&text)?;
+
     Ok(())
 };
 
@@ -65,87 +73,128 @@ const STORE: Operation = |simulator| {
     Ok(())
 };
 
+const LOAD_INDEXED: Operation = |simulator| {
+    let address = simulator.indexed_address()?;
+    simulator.accumulator = simulator.memory[address];
+
+    Ok(())
+};
+
+const STORE_INDEXED: Operation = |simulator| {
+    let address = simulator.indexed_address()?;
+    simulator.memory[address] = simulator.accumulator;
+
+    Ok(())
+};
+
+const SET_INDEX: Operation = |simulator| {
+    simulator.index_register = match simulator.memory[simulator.operand as usize] {
+        Word::Int(index) => index,
+        Word::Float(index) => bail!("Array index {:?} is not an integer", index),
+    };
+
+    Ok(())
+};
+
 const ADD: Operation = |simulator| {
-    simulator.accumulator += simulator.memory[simulator.operand as usize];
+    simulator.accumulator = simulator
+        .accumulator
+        .try_add(simulator.memory[simulator.operand as usize])?;
 
     Ok(())
 };
 
 const SUBTRACT: Operation = |simulator| {
-    simulator.accumulator -= simulator.memory[simulator.operand as usize];
+    simulator.accumulator = simulator
+        .accumulator
+        .try_subtract(simulator.memory[simulator.operand as usize])?;
 
     Ok(())
 };
 
 const DIVIDE: Operation = |simulator| {
-    if simulator.memory[simulator.operand as usize] == 0 {
+    if simulator.memory[simulator.operand as usize].is_zero() {
         bail!("Attempt to divide by zero");
     }
 
-    simulator.accumulator /= simulator.memory[simulator.operand as usize];
+    simulator.accumulator = simulator
+        .accumulator
+        .try_divide(simulator.memory[simulator.operand as usize])?;
 
     Ok(())
 };
 
 const MULTIPLY: Operation = |simulator| {
-    simulator.accumulator *= simulator.memory[simulator.operand as usize];
+    simulator.accumulator = simulator
+        .accumulator
+        .try_multiply(simulator.memory[simulator.operand as usize])?;
 
     Ok(())
 };
 
 const MODULUS: Operation = |simulator| {
-    if simulator.memory[simulator.operand as usize] == 0 {
+    if simulator.memory[simulator.operand as usize].is_zero() {
         bail!("Attempt to modulo by zero");
     }
 
-    simulator.accumulator %= simulator.memory[simulator.operand as usize];
+    simulator.accumulator = simulator
+        .accumulator
+        .try_modulus(simulator.memory[simulator.operand as usize])?;
 
     Ok(())
 };
 
 const EXPONENTIATE: Operation = |simulator| {
-    let base = simulator.accumulator;
-    simulator.accumulator = 1;
-
-    for _ in 0..simulator.memory[simulator.operand as usize] {
-        simulator.accumulator *= base;
-    }
+    simulator.accumulator = simulator
+        .accumulator
+        .try_exponentiate(simulator.memory[simulator.operand as usize])?;
 
     Ok(())
 };
 
 const BRANCH: Operation = |simulator| {
     // go to one instruction before because it will be incremented
-    simulator.set_instruction_counter(simulator.operand - 1);
+    simulator.set_instruction_counter(simulator.operand.wrapping_sub(1));
 
     Ok(())
 };
 
 const BRANCH_NEG: Operation = |simulator| {
-    if simulator.accumulator < 0 {
-        simulator.set_instruction_counter(simulator.operand - 1);
+    if simulator.accumulator.is_negative() {
+        simulator.set_instruction_counter(simulator.operand.wrapping_sub(1));
     }
 
     Ok(())
 };
 
 const BRANCH_ZERO: Operation = |simulator| {
-    if simulator.accumulator == 0 {
-        simulator.set_instruction_counter(simulator.operand - 1);
+    if simulator.accumulator.is_zero() {
+        simulator.set_instruction_counter(simulator.operand.wrapping_sub(1));
     }
 
     Ok(())
 };
 
 const HALT: Operation = |simulator| {
-    println!();
-    println!("*** Simpletron execution terminated ***");
-
     simulator.set_state(State::Halted);
 
     Ok(())
 };
 
+const CALL: Operation = |simulator| {
+    // remember this instruction so RETURN resumes after it
+    simulator.push_return_address()?;
+    simulator.set_instruction_counter(simulator.operand.wrapping_sub(1));
+
+    Ok(())
+};
+
+const RETURN: Operation = |simulator| {
+    simulator.pop_return_address()?;
+
+    Ok(())
+};
+
 const SML_DEBUG: Operation = |simulator| {
     simulator.set_debug(simulator.operand != 0);
 
@@ -159,8 +208,12 @@ lazy_static! {
         (0x11, WRITE),
         (0x12, READ_STR),
         (0x13, WRITE_STR),
+        (0x14, READ_STR_BUFFER),
         (0x20, LOAD),
         (0x21, STORE),
+        (0x22, LOAD_INDEXED),
+        (0x23, STORE_INDEXED),
+        (0x24, SET_INDEX),
         (0x30, ADD),
         (0x31, SUBTRACT),
         (0x32, DIVIDE),
@@ -172,5 +225,38 @@ lazy_static! {
         (0x42, BRANCH_ZERO),
         (0x43, HALT),
         (0x44, SML_DEBUG),
+        (0x45, CALL),
+        (0x46, RETURN),
     ]);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{BufferIo, Outcome, Program, Simulator};
+
+    fn run(sml: &str, input: &str) -> String {
+        let program = Program::from_sml(sml).unwrap();
+
+        let mut io = BufferIo::new(input);
+        let mut simulator = Simulator::with_io(&mut io);
+        simulator.load(&program).unwrap();
+        assert_eq!(simulator.simulate(), Outcome::Halted);
+        drop(simulator);
+
+        io.output().to_string()
+    }
+
+    #[test]
+    fn read_str_and_write_str_keep_the_whole_string_on_one_line() {
+        assert_eq!(
+            run("120005\n130005\n130005\n430000\n", "hello world\n"),
+            "hello worldhello world"
+        );
+    }
+
+    #[test]
+    fn read_str_buffer_keeps_what_fits_the_capacity() {
+        // capacity 3 at address 5, length at address 6
+        assert_eq!(run("140006\n130006\n430000\n0\n0\n3\n", "hello\n"), "hel");
+    }
+}
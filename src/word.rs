@@ -0,0 +1,166 @@
+//! Memory words and the arithmetic the Simpletron does on them.
+
+use crate::config::INSTRUCTIONS_RADIX;
+use anyhow::{bail, Result};
+use std::fmt;
+
+// prefix marking a real number in SML files, which cannot appear in a hexadecimal word
+const FLOAT_PREFIX: &str = "f:";
+
+/// A single memory word, which holds an integer (or instruction) or a real number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Word {
+    /// An integer, or an instruction made of an operation code and an operand.
+    Int(i32),
+    /// A real number.
+    Float(f64),
+}
+
+impl Word {
+    /// Parses a number as written in a Simple program or typed as input.
+    pub fn parse_decimal(token: &str) -> Result<Word> {
+        if let Ok(number) = token.parse::<i32>() {
+            return Ok(Word::Int(number));
+        }
+
+        // only accept real numbers that start like a number, so inf and NaN stay names
+        let starts_numeric = token
+            .trim_start_matches(['-', '+'])
+            .starts_with(|x: char| x.is_ascii_digit() || x == '.');
+
+        match token.parse::<f64>() {
+            Ok(number) if starts_numeric => Ok(Word::Float(number)),
+            _ => bail!("Invalid number {}", token),
+        }
+    }
+
+    /// Parses a word from a line of an SML file.
+    pub fn from_sml(token: &str) -> Result<Word> {
+        if let Some(number) = token.strip_prefix(FLOAT_PREFIX) {
+            return match number.parse::<f64>() {
+                Ok(number) => Ok(Word::Float(number)),
+                Err(_) => bail!("Invalid real number {}", number),
+            };
+        }
+
+        match i32::from_str_radix(token, INSTRUCTIONS_RADIX) {
+            Ok(number) => Ok(Word::Int(number)),
+            Err(_) => bail!("Invalid word {}", token),
+        }
+    }
+
+    /// Formats a word for a line of an SML file.
+    pub fn to_sml(&self) -> String {
+        match self {
+            // keep the sign so negative constants can be loaded back
+            Word::Int(x) if *x < 0 => format!("-{:x}", x.unsigned_abs()),
+            Word::Int(x) => format!("{x:x}"),
+            Word::Float(x) => format!("{FLOAT_PREFIX}{x:?}"),
+        }
+    }
+
+    /// The integer in the word, failing for a real number.
+    pub fn as_int(&self) -> Result<i32> {
+        match self {
+            Word::Int(x) => Ok(*x),
+            Word::Float(x) => bail!("Expected an integer, found {:?}", x),
+        }
+    }
+
+    /// The word as a real number.
+    pub fn as_float(&self) -> f64 {
+        match self {
+            Word::Int(x) => *x as f64,
+            Word::Float(x) => *x,
+        }
+    }
+
+    /// Whether the word is zero, as tested by BRANCH_ZERO.
+    pub fn is_zero(&self) -> bool {
+        self.as_float() == 0.0
+    }
+
+    /// Whether the word is negative, as tested by BRANCH_NEG.
+    pub fn is_negative(&self) -> bool {
+        self.as_float() < 0.0
+    }
+
+    /// Adds two words, failing on integer overflow.
+    ///
+    /// Integers stay integers, and any real operand makes the result real, which is
+    /// also true of the other arithmetic operations.
+    pub fn try_add(self, other: Word) -> Result<Word> {
+        self.arithmetic(other, i32::checked_add, |x, y| x + y)
+    }
+
+    /// Subtracts a word, failing on integer overflow.
+    pub fn try_subtract(self, other: Word) -> Result<Word> {
+        self.arithmetic(other, i32::checked_sub, |x, y| x - y)
+    }
+
+    /// Multiplies two words, failing on integer overflow.
+    pub fn try_multiply(self, other: Word) -> Result<Word> {
+        self.arithmetic(other, i32::checked_mul, |x, y| x * y)
+    }
+
+    /// Divides by a word, truncating integers. Callers check for a zero divisor.
+    pub fn try_divide(self, other: Word) -> Result<Word> {
+        self.arithmetic(other, i32::checked_div, |x, y| x / y)
+    }
+
+    /// The remainder of dividing by a word. Callers check for a zero divisor.
+    pub fn try_modulus(self, other: Word) -> Result<Word> {
+        self.arithmetic(other, i32::checked_rem, |x, y| x % y)
+    }
+
+    /// Raises to the power of a word, failing for a negative integer exponent of an
+    /// integer or on overflow.
+    pub fn try_exponentiate(self, other: Word) -> Result<Word> {
+        match (self, other) {
+            // the result of a negative integer exponent is not an integer
+            (Word::Int(_), Word::Int(y)) if y < 0 => {
+                bail!("Attempt to raise an integer to negative exponent {}", y)
+            }
+            (Word::Int(x), Word::Int(y)) => match x.checked_pow(y as u32) {
+                Some(result) => Ok(Word::Int(result)),
+                None => bail!("Integer overflow"),
+            },
+            _ => Ok(Word::Float(self.as_float().powf(other.as_float()))),
+        }
+    }
+
+    // integers stay integers, and any real operand makes the result real
+    fn arithmetic(
+        self,
+        other: Word,
+        int_operation: fn(i32, i32) -> Option<i32>,
+        float_operation: fn(f64, f64) -> f64,
+    ) -> Result<Word> {
+        match (self, other) {
+            (Word::Int(x), Word::Int(y)) => match int_operation(x, y) {
+                Some(result) => Ok(Word::Int(result)),
+                None => bail!("Integer overflow"),
+            },
+            _ => Ok(Word::Float(float_operation(
+                self.as_float(),
+                other.as_float(),
+            ))),
+        }
+    }
+}
+
+impl Default for Word {
+    fn default() -> Self {
+        Word::Int(0)
+    }
+}
+
+// output format used by WRITE, where real numbers always show a decimal point
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Word::Int(x) => write!(f, "{x}"),
+            Word::Float(x) => write!(f, "{x:?}"),
+        }
+    }
+}